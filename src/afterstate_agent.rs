@@ -0,0 +1,151 @@
+/*
+Afterstate learning.
+In placement games the board right after our move decides everything that follows,
+so the agent learns one value per afterstate instead of one per state/action pair.
+Moves that reach the same board, from different boards or in a different order, share what they learn.
+*/
+
+use std::any::Any;
+use std::collections::HashMap;
+use bracket_lib::random::RandomNumberGenerator;
+
+use crate::agent::{Agent, Params};
+use crate::exploration::{self, Exploration};
+use crate::piece::Piece;
+use crate::q_agent::{Value, Frequency};
+use crate::rules::Rules;
+use crate::state::*;
+use crate::symmetry::Symmetries;
+
+type VTable = HashMap<State, (Value, Frequency), KeyHash>;
+
+pub struct AfterstateAgent {
+    v_table:    VTable,
+    rules:      Rules,
+    player:     Piece,
+    symmetries: Symmetries,
+    exploration: Exploration,
+    // Our last afterstate and its reward, waiting for the value of the next one.
+    pending:    Option<(State, Value)>,
+}
+
+impl AfterstateAgent {
+    pub fn new(rules: Rules, player: Piece) -> AfterstateAgent {
+        let symmetries = Symmetries::new(rules, false);
+        AfterstateAgent { v_table: VTable::default(), rules, player, symmetries,
+            exploration: Exploration::EpsilonGreedy, pending: None }
+    }
+
+    /// Stores every afterstate under its canonical rotation/reflection.
+    pub fn with_symmetry(mut self, enabled: bool) -> AfterstateAgent {
+        self.symmetries = Symmetries::new(self.rules, enabled);
+        self
+    }
+
+    /// Chooses how moves are picked while learning, from the values of their afterstates.
+    pub fn with_exploration(mut self, exploration: Exploration) -> AfterstateAgent {
+        self.exploration = exploration;
+        self
+    }
+
+    /// The board an action leaves behind.
+    fn afterstate(state: &State, action: &Action) -> State {
+        let mut after = state.clone();
+        after.play_mut(action);
+        after
+    }
+
+    /// Looks up the value of an afterstate, 0 if it has never been updated.
+    fn value(&self, after: &State) -> Value {
+        let (canonical, _) = self.symmetries.canonical(after);
+        self.v_table.get(&canonical).map_or(0., |(v, _frq)| *v)
+    }
+
+    /// How often the afterstate of each legal action has been updated, in the order of `q_values`.
+    fn counts(&self, state: &State) -> Vec<Frequency> {
+        state.legal_actions(self.player).iter()
+            .map(|action| {
+                let (canonical, _) = self.symmetries.canonical(&Self::afterstate(state, action));
+                self.v_table.get(&canonical).map_or(0, |(_v, frq)| *frq)
+            })
+            .collect()
+    }
+
+    /// Moves the value of an afterstate toward a target.
+    fn update(&mut self, after: &State, target: Value, eta: Value) {
+        let (canonical, _) = self.symmetries.canonical(after);
+        match self.v_table.get_mut(&canonical) {
+            None => {
+                self.v_table.insert(canonical, (eta * target, 1));
+            },
+            Some((v, freq)) => {
+                *v += eta * (target - *v);
+                *freq += 1;
+            },
+        }
+    }
+
+    /// The legal action with the most valuable afterstate, and that value.
+    fn best_action(&self, state: &State) -> Option<(Action, Value)> {
+        let mut best: Option<(Action, Value)> = None;
+        for action in state.legal_actions(self.player) {
+            let v = self.value(&Self::afterstate(state, &action));
+            if best.is_none_or(|(_, best_v)| v > best_v) {
+                best = Some((action, v));
+            }
+        }
+        best
+    }
+}
+
+impl Agent for AfterstateAgent {
+    fn player(&self) -> Piece {
+        self.player
+    }
+
+    /// Scores each legal move by the board it leaves and explores around those values.
+    /// The previous afterstate bootstraps from the best one reachable now.
+    fn choose_action(&mut self, state: &State, rng: &mut RandomNumberGenerator, params: &Params) -> Option<Action> {
+        let (_, best_v) = self.best_action(state).expect("No legal actions");
+        if let Some((after, reward)) = self.pending.take() {
+            self.update(&after, reward + params.gamma * best_v, params.eta);
+        }
+
+        let values = self.q_values(state);
+        let greedy = exploration::greedy(&values, rng);
+        Some(self.exploration.choose(&values, &self.counts(state), greedy, rng, params))
+    }
+
+    /// The state after our own move is the afterstate.
+    /// Once the game is over the reward is the whole target, for the finishing move itself
+    /// or, when the opponent finished it, for our last afterstate.
+    fn observe(&mut self, _prev_state: &State, _action: &Action, reward: Value, state: &State,
+        _rng: &mut RandomNumberGenerator, params: &Params) {
+        if state.check_winner() != Piece::Empty {
+            let after = self.pending.take().map_or_else(|| state.clone(), |(after, _)| after);
+            self.update(&after, reward, params.eta);
+        }
+        else {
+            self.pending = Some((state.clone(), reward));
+        }
+    }
+
+    fn observe_end(&mut self, _state: &State, _winner: Piece, _params: &Params) {
+        self.pending = None;
+    }
+
+    fn greedy_action(&self, state: &State) -> Option<Action> {
+        self.best_action(state).map(|(action, _)| action)
+    }
+
+    /// Lists the value of the afterstate of every legal action.
+    fn q_values(&self, state: &State) -> Vec<(Action, Value)> {
+        state.legal_actions(self.player).into_iter()
+            .map(|action| (action, self.value(&Self::afterstate(state, &action))))
+            .collect()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
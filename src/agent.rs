@@ -0,0 +1,110 @@
+/*
+Agent is anything that can take a seat in a Game:
+it picks moves, and may learn from what happens after them.
+*/
+
+use std::any::Any;
+use bracket_lib::random::RandomNumberGenerator;
+
+use crate::piece::Piece;
+use crate::q_agent::{Value, Frequency};
+use crate::replay::ReplayBuffer;
+use crate::reward::RewardScheme;
+use crate::state::*;
+
+/// Learning parameters the game hands to every agent each turn.
+#[derive(Clone, Copy)]
+pub struct Params {
+    pub epsilon:    Value,
+    pub eta:        Value,
+    pub gamma:      Value,
+    /// Trace decay for agents with eligibility traces.
+    pub lambda:     Value,
+    /// Softmax exploration temperature.
+    pub temperature: Value,
+    /// Scales of inverse-visit-count schedules on epsilon and the temperature, which divide them
+    /// by 1 + N(s) / scale on a state the agent has played N(s) times.
+    pub epsilon_visits:     Option<Value>,
+    pub temperature_visits: Option<Value>,
+    /// What the game pays out when it ends.
+    pub rewards:    RewardScheme,
+}
+
+impl Params {
+    /// The parameters on a state the agent has played this many times.
+    pub fn at_visits(&self, visits: Frequency) -> Params {
+        let decay = |scale: Option<Value>| scale.map_or(1., |scale| 1. / (1. + visits as Value / scale));
+        Params { epsilon: self.epsilon * decay(self.epsilon_visits),
+            temperature: self.temperature * decay(self.temperature_visits), ..*self }
+    }
+}
+
+pub trait Agent {
+    /// The piece this agent plays as.
+    fn player(&self) -> Piece;
+
+    /// Picks the action to play on the given state, or None to give up the game,
+    /// e.g. when a human closes the input.
+    fn choose_action(&mut self, state: &State, rng: &mut RandomNumberGenerator, params: &Params) -> Option<Action>;
+
+    /// Sees the result of one of its own actions, with the board it left,
+    /// once its next turn or the end of the game has settled the reward.
+    /// When the opponent ends the game, its last action is observed again with the final board
+    /// and the reward for the result, which replaces the earlier observation of that action.
+    /// Learners hold each update until their next turn or the end of the game for this,
+    /// so the action gets one update and one visit.
+    fn observe(&mut self, _prev_state: &State, _action: &Action, _reward: Value, _state: &State,
+        _rng: &mut RandomNumberGenerator, _params: &Params) {}
+
+    /// Sees the final board once the game is over.
+    fn observe_end(&mut self, _state: &State, _winner: Piece, _params: &Params) {}
+
+    /// Learns from a mini-batch of remembered transitions, between games.
+    fn replay(&mut self, _buffer: &mut ReplayBuffer, _rng: &mut RandomNumberGenerator, _params: &Params) {}
+
+    /// The action the agent would play with no exploration, if it has a fixed policy.
+    /// Used to measure the quality of what it has learned, so ties go to the first action
+    /// where play breaks them at random.
+    fn greedy_action(&self, _state: &State) -> Option<Action> {
+        None
+    }
+
+    /// Q values of the legal actions, for showing to a human. Empty if the agent has none.
+    fn q_values(&self, _state: &State) -> Vec<(Action, Value)> {
+        Vec::new()
+    }
+
+    /// Is this seat played by a person at the terminal?
+    fn is_human(&self) -> bool {
+        false
+    }
+
+    /// Allows getting the concrete agent back out of a Game, e.g. to save it.
+    fn as_any(&self) -> &dyn Any;
+}
+
+/// Plays uniformly at random among the legal actions and never learns.
+pub struct RandomAgent {
+    player: Piece,
+}
+
+impl RandomAgent {
+    pub fn new(player: Piece) -> RandomAgent {
+        RandomAgent { player }
+    }
+}
+
+impl Agent for RandomAgent {
+    fn player(&self) -> Piece {
+        self.player
+    }
+
+    fn choose_action(&mut self, state: &State, rng: &mut RandomNumberGenerator, _params: &Params) -> Option<Action> {
+        let actions = state.legal_actions(self.player);
+        Some(actions[rng.range(0, actions.len())])
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
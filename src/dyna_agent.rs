@@ -0,0 +1,203 @@
+/*
+Dyna-Q, model-based planning on top of a Q-learning agent.
+The agent remembers which board followed each of its moves, once the opponent has replied,
+and after every real update it replays remembered moves against that model.
+Tic-tac-toe is deterministic apart from the opponent's reply, so a few hundred games
+build a model good enough to do most of the learning in simulation.
+*/
+
+use std::any::Any;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use bracket_lib::random::RandomNumberGenerator;
+
+use crate::agent::{Agent, Params};
+use crate::piece::Piece;
+use crate::q_agent::{QLearningAgent, Value, Frequency};
+use crate::replay::ReplayBuffer;
+use crate::state::*;
+
+// Every board seen after each move, with the move's reward and how often it happened.
+// Moves and boards are keyed on the canonical board, as in the agent's table.
+type Model = HashMap<(State, Action), Vec<(State, Value, Frequency)>, KeyHash>;
+
+/// Which remembered moves the simulated updates replay.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Planning {
+    /// Moves picked uniformly at random among every one seen so far.
+    Uniform,
+    /// Prioritized sweeping: the moves whose value is furthest off first,
+    /// then the moves leading to a board whose value just changed.
+    /// Moves off by less than the threshold are not queued.
+    Sweeping { threshold: Value },
+}
+
+/// A move waiting to be replayed, ordered by how far its value is off.
+struct Priority(Value, (State, Action));
+
+impl PartialEq for Priority {
+    fn eq(&self, other: &Priority) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Priority {}
+
+impl PartialOrd for Priority {
+    fn partial_cmp(&self, other: &Priority) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Priority {
+    fn cmp(&self, other: &Priority) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+pub struct DynaAgent {
+    agent:      QLearningAgent,
+    // Simulated updates after every real one.
+    steps:      usize,
+    planning:   Planning,
+    model:      Model,
+    // The moves in the model, to sample from.
+    seen:       Vec<(State, Action)>,
+    // The moves that have led to each canonical board.
+    predecessors: HashMap<State, Vec<(State, Action)>, KeyHash>,
+    queue:      BinaryHeap<Priority>,
+    // Our last move and its reward, waiting for the opponent's reply.
+    pending:    Option<(State, Action, Value)>,
+}
+
+impl DynaAgent {
+    /// Plans with the given agent's Q-table, running `steps` simulated updates after every real one.
+    pub fn new(agent: QLearningAgent, steps: usize) -> DynaAgent {
+        DynaAgent { agent, steps, planning: Planning::Uniform, model: Model::default(), seen: Vec::new(),
+            predecessors: HashMap::default(), queue: BinaryHeap::new(), pending: None }
+    }
+
+    /// Chooses which remembered moves to replay.
+    pub fn with_planning(mut self, planning: Planning) -> DynaAgent {
+        self.planning = planning;
+        self
+    }
+
+    /// The agent doing the learning, e.g. to save it.
+    pub fn agent(&self) -> &QLearningAgent {
+        &self.agent
+    }
+
+    /// Remembers that a move led to a board, then plans.
+    fn record(&mut self, state: State, action: Action, reward: Value, next: State,
+        rng: &mut RandomNumberGenerator, params: &Params) {
+        let k = self.agent.symmetries().key(&state, &action);
+        let next = self.agent.symmetries().canonical(&next).0;
+        let outcomes = self.model.entry(k.clone()).or_default();
+        if outcomes.is_empty() {
+            self.seen.push(k.clone());
+        }
+        match outcomes.iter_mut().find(|(s, r, _)| *s == next && *r == reward) {
+            Some((_, _, frequency)) => *frequency += 1,
+            None => {
+                outcomes.push((next.clone(), reward, 1));
+                self.predecessors.entry(next).or_default().push(k.clone());
+            },
+        }
+        if let Planning::Sweeping { threshold } = self.planning {
+            self.enqueue(k, threshold, params.gamma);
+        }
+        self.plan(rng, params);
+    }
+
+    /// The expected target of a move under the model, over every board that has followed it.
+    fn target(&self, k: &(State, Action), gamma: Value) -> Value {
+        let outcomes = &self.model[k];
+        let total: Frequency = outcomes.iter().map(|(_, _, frequency)| frequency).sum();
+        outcomes.iter()
+            .map(|(next, reward, frequency)| {
+                (reward + gamma * self.agent.state_value(next)) * *frequency as Value / total as Value
+            })
+            .sum()
+    }
+
+    /// Queues a move if its value is off from the model's target by more than the threshold.
+    fn enqueue(&mut self, k: (State, Action), threshold: Value, gamma: Value) {
+        let error = (self.target(&k, gamma) - self.agent.q_value(&k.0, &k.1)).abs();
+        if error > threshold {
+            self.queue.push(Priority(error, k));
+        }
+    }
+
+    /// Runs the simulated updates.
+    fn plan(&mut self, rng: &mut RandomNumberGenerator, params: &Params) {
+        for _ in 0..self.steps {
+            let k = match self.planning {
+                Planning::Uniform => self.seen[rng.range(0, self.seen.len())].clone(),
+                Planning::Sweeping { .. } => match self.queue.pop() {
+                    Some(Priority(_, k)) => k,
+                    None => return,
+                },
+            };
+            let target = self.target(&k, params.gamma);
+            self.agent.update_toward(&k.0, &k.1, target, rng, params);
+
+            // The value of the board before this move may have changed, so its predecessors may be off.
+            if let Planning::Sweeping { threshold } = self.planning {
+                let predecessors = self.predecessors.get(&k.0).cloned().unwrap_or_default();
+                for predecessor in predecessors {
+                    self.enqueue(predecessor, threshold, params.gamma);
+                }
+            }
+        }
+    }
+}
+
+impl Agent for DynaAgent {
+    fn player(&self) -> Piece {
+        self.agent.player()
+    }
+
+    /// The board we see now is where our last move led, so it goes into the model first.
+    fn choose_action(&mut self, state: &State, rng: &mut RandomNumberGenerator, params: &Params) -> Option<Action> {
+        if let Some((prev_state, action, reward)) = self.pending.take() {
+            self.record(prev_state, action, reward, state.clone(), rng, params);
+        }
+        self.agent.choose_action(state, rng, params)
+    }
+
+    /// Learns from the real move, then models it once the opponent has replied or the game is over.
+    fn observe(&mut self, prev_state: &State, action: &Action, reward: Value, state: &State,
+        rng: &mut RandomNumberGenerator, params: &Params) {
+        self.agent.observe(prev_state, action, reward, state, rng, params);
+        if state.check_winner() != Piece::Empty {
+            // A finished game replaces the move still waiting on a reply.
+            self.pending = None;
+            self.record(prev_state.clone(), *action, reward, state.clone(), rng, params);
+        }
+        else {
+            self.pending = Some((prev_state.clone(), *action, reward));
+        }
+    }
+
+    fn observe_end(&mut self, state: &State, winner: Piece, params: &Params) {
+        self.pending = None;
+        self.agent.observe_end(state, winner, params);
+    }
+
+    fn replay(&mut self, buffer: &mut ReplayBuffer, rng: &mut RandomNumberGenerator, params: &Params) {
+        self.agent.replay(buffer, rng, params);
+    }
+
+    fn greedy_action(&self, state: &State) -> Option<Action> {
+        self.agent.greedy_action(state)
+    }
+
+    fn q_values(&self, state: &State) -> Vec<(Action, Value)> {
+        self.agent.q_values(state)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
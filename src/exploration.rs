@@ -0,0 +1,136 @@
+/*
+Exploration strategies, how an agent picks a move from the Q values of the legal actions.
+*/
+
+use bracket_lib::random::RandomNumberGenerator;
+
+use crate::agent::Params;
+use crate::q_agent::{Value, Frequency};
+use crate::state::Action;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Exploration {
+    /// The greedy action, or with probability epsilon a uniformly random one.
+    EpsilonGreedy,
+    /// Samples each action with probability proportional to exp(Q / temperature),
+    /// so clearly bad moves are rarely tried. A temperature of zero or below plays greedily.
+    Softmax,
+    /// The action with the best Q + c * sqrt(ln N(s) / N(s, a)), from the visit counts.
+    /// Actions never tried come first, so exploration is directed rather than random.
+    Ucb { c: Value },
+}
+
+/// The legal action with the best Q value, ties broken at random.
+pub fn greedy(q_values: &[(Action, Value)], rng: &mut RandomNumberGenerator) -> Action {
+    // Keeps the n-th tie with probability 1/n, so every tie is equally likely without collecting them.
+    let mut best = q_values[0];
+    let mut ties = 1;
+    for &(action, q) in &q_values[1..] {
+        if q > best.1 {
+            best = (action, q);
+            ties = 1;
+        }
+        else if q == best.1 {
+            ties += 1;
+            if rng.range(0, ties) == 0 {
+                best.0 = action;
+            }
+        }
+    }
+    best.0
+}
+
+impl Exploration {
+    /// Picks one of the legal actions, given with their Q values and visit counts.
+    /// `greedy` is the action the agent considers best.
+    /// The counts add up to the visits of the state, for inverse-visit-count schedules.
+    pub fn choose(&self, q_values: &[(Action, Value)], counts: &[Frequency], greedy: Action,
+        rng: &mut RandomNumberGenerator, params: &Params) -> Action {
+        let params = &params.at_visits(counts.iter().sum());
+        match self {
+            Exploration::EpsilonGreedy => {
+                if rng.range(0., 1.) > params.epsilon {
+                    greedy
+                }
+                else {
+                    q_values[rng.range(0, q_values.len())].0
+                }
+            },
+            Exploration::Ucb { c } => {
+                let untried: Vec<Action> = q_values.iter().zip(counts)
+                    .filter(|(_, n)| **n == 0)
+                    .map(|((action, _), _)| *action)
+                    .collect();
+                if untried.is_empty() {
+                    Self::ucb_best(q_values, counts, *c)
+                }
+                else {
+                    untried[rng.range(0, untried.len())]
+                }
+            },
+            Exploration::Softmax if params.temperature <= 0. => greedy,
+            Exploration::Softmax => {
+                let probabilities = self.probabilities(q_values, counts, greedy, params);
+                let mut p = rng.range(0., 1.);
+                for ((action, _), probability) in q_values.iter().zip(probabilities) {
+                    if p < probability {
+                        return *action;
+                    }
+                    p -= probability;
+                }
+                // Rounding left a sliver of probability over.
+                q_values[q_values.len() - 1].0
+            },
+        }
+    }
+
+    /// The probability of choosing each of the legal actions, in the order given.
+    pub fn probabilities(&self, q_values: &[(Action, Value)], counts: &[Frequency], greedy: Action,
+        params: &Params) -> Vec<Value> {
+        let params = &params.at_visits(counts.iter().sum());
+        match self {
+            Exploration::EpsilonGreedy => {
+                let explore = params.epsilon / q_values.len() as Value;
+                q_values.iter()
+                    .map(|(action, _)| if *action == greedy { explore + 1. - params.epsilon } else { explore })
+                    .collect()
+            },
+            Exploration::Softmax if params.temperature <= 0. => {
+                q_values.iter().map(|(action, _)| if *action == greedy { 1. } else { 0. }).collect()
+            },
+            Exploration::Softmax => {
+                // Shift by the best value so exp cannot overflow at low temperatures.
+                let best = q_values.iter().map(|(_, q)| *q).fold(Value::NEG_INFINITY, Value::max);
+                let weights: Vec<Value> = q_values.iter()
+                    .map(|(_, q)| ((q - best) / params.temperature).exp())
+                    .collect();
+                let total: Value = weights.iter().sum();
+                weights.into_iter().map(|w| w / total).collect()
+            },
+            Exploration::Ucb { c } => {
+                let untried = counts.iter().filter(|n| **n == 0).count();
+                if untried > 0 {
+                    return counts.iter()
+                        .map(|n| if *n == 0 { 1. / untried as Value } else { 0. })
+                        .collect();
+                }
+                let best = Self::ucb_best(q_values, counts, *c);
+                q_values.iter().map(|(action, _)| if *action == best { 1. } else { 0. }).collect()
+            },
+        }
+    }
+
+    /// The action with the highest upper confidence bound, when every action has been tried.
+    fn ucb_best(q_values: &[(Action, Value)], counts: &[Frequency], c: Value) -> Action {
+        let total: Frequency = counts.iter().sum();
+        let ln_total = (total as Value).ln();
+        let mut best = (q_values[0].0, Value::NEG_INFINITY);
+        for ((action, q), n) in q_values.iter().zip(counts) {
+            let bound = q + c * (ln_total / *n as Value).sqrt();
+            if bound > best.1 {
+                best = (*action, bound);
+            }
+        }
+        best.0
+    }
+}
@@ -1,138 +1,217 @@
-use crate::q_agent::QLearningAgent;
+use crate::agent::{Agent, Params};
 use crate::q_agent::Value;
 use crate::piece::Piece;
-use crate::vector::Vector2;
+use crate::rules::Rules;
 use crate::state::*;
+use crate::human;
+use crate::reward::RewardScheme;
+use crate::replay::{ReplayBuffer, Transition};
+use crate::schedule::{Schedules, Tick};
 use bracket_lib::random::RandomNumberGenerator;
 use ndarray::prelude::*;
 
 pub struct Game {
-    player:     Array1<QLearningAgent>,
+    player:     Array1<Box<dyn Agent>>,
     board:      State,
     rng:        RandomNumberGenerator,
-    eta:        Value,
-    gamma:      Value,
-    pub epsilon:    Value,
+    pub params: Params,
+    schedules:  Schedules,
+    // Evaluation games leave the schedules where training left them.
+    paused:     bool,
+    // Each seat's memory of its own moves, for agents that replay them.
+    replay:     [Option<ReplayBuffer>; 2],
+    // Each seat's last move and the board it left, observed once the seat's next turn
+    // shows where the move led, so every reward runs from one of its turns to the next.
+    waiting:    [Option<(State, Action, State)>; 2],
 }
 
 impl Game {
-    pub fn new(board_size: Vector2, epsilon: Value, eta: Value, gamma: Value) -> Game {
-        // Set up players.
-        let mut player_vec = Vec::new();
-        for piece in vec![Piece::P1, Piece::P2].iter() {
-            let p = QLearningAgent::new(board_size, *piece);
-            player_vec.push(p);
-        }
-        
-        let player = Array1::from_vec(player_vec);
-        let board = State::new(board_size);
+    /// Seats two agents, one playing as P1 and the other as P2.
+    pub fn new(rules: Rules, p1: Box<dyn Agent>, p2: Box<dyn Agent>,
+        epsilon: Value, eta: Value, gamma: Value, lambda: Value) -> Game {
+        assert!(p1.player() == Piece::P1 && p2.player() == Piece::P2, "agents are in the wrong seats");
+        let player = Array1::from_vec(vec![p1, p2]);
+        let board = State::new(rules);
         let rng = RandomNumberGenerator::new();
-        Game { player, board, rng, epsilon, eta, gamma }
+        let params = Params { epsilon, eta, gamma, lambda, temperature: 1.,
+            epsilon_visits: None, temperature_visits: None, rewards: RewardScheme::default() };
+        Game { player, board, rng, params, schedules: Schedules::default(), paused: false, replay: [None, None],
+            waiting: [None, None] }
+    }
+
+    /// The agent playing as the given piece.
+    pub fn agent(&self, player: Piece) -> &dyn Agent {
+        self.player[player.to_index()].as_ref()
+    }
+
+    /// Replaces the agent in the seat it plays for, e.g. with one loaded from disk.
+    /// Returns the agent that was sitting there.
+    pub fn set_agent(&mut self, agent: Box<dyn Agent>) -> Box<dyn Agent> {
+        let index = agent.player().to_index();
+        std::mem::replace(&mut self.player[index], agent)
+    }
+
+    /// Follows the given schedules from their start, overriding the parameters they cover.
+    pub fn set_schedules(&mut self, schedules: Schedules) {
+        self.schedules = schedules;
+        self.schedules.apply(&mut self.params);
+    }
+
+    /// Remembers the moves of the given seat, which replays a mini-batch of them after every game.
+    pub fn set_replay(&mut self, player: Piece, buffer: ReplayBuffer) {
+        self.replay[player.to_index()] = Some(buffer);
+    }
+
+    /// Stops or restarts the schedules counting steps and games, e.g. while testing.
+    pub fn pause_schedules(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    /// Advances the schedules that count in epochs.
+    pub fn end_epoch(&mut self) {
+        self.schedules.advance(Tick::Epoch, &mut self.params);
     }
 
     /// Resets the board in-place.
     pub fn reset(&mut self) {
         self.board.reset();
+        self.waiting = [None, None];
     }
 
     /// Plays out a single game, returning the winner.
-    pub fn play(&mut self, print: bool, agent_player: Piece, _human_player: Piece) -> Piece {
+    /// Returns Empty without learning anything if a player gives up before the end.
+    pub fn play(&mut self, print: bool) -> Piece {
         // Stores all of the states and vectors of a game.
         let mut state_history = Vec::<State>::with_capacity(10);
         let mut action_history = Vec::<Action>::with_capacity(9);
+        let human = self.player.iter().any(|p| p.is_human());
         
         state_history.push(self.board.clone());
         let mut winner = Piece::Empty;
 
         while winner == Piece::Empty {
-            // agent_turn mutates the board state in-place
-            let on_play = self.board.on_play();
-            let temp = {
-                if agent_player == on_play {
-                    self.agent_turn()
-                }
-                else {
-                    // choose a random move
-                    let action = self.player[on_play.to_index()].choose_random_action(&mut self.rng);
-                    let winner = Self::update_board(&mut self.board, &action).0;
-                    (winner, action)
-                }
+            // take_turn mutates the board state in-place
+            let turn = if human {
+                self.shown_turn()
+            }
+            else {
+                self.take_turn()
             };
+            let Some(temp) = turn else { return Piece::Empty };
             state_history.push(self.board.clone());
             action_history.push(temp.1);
             winner = temp.0;
         }
 
+        if human {
+            println!("{}", human::render(&self.board));
+            match winner {
+                Piece::Draw => println!("Draw."),
+                _ => println!("{} wins.", winner),
+            }
+        }
+
         // Print
         if print {
             for state in state_history.iter() {
-                println!("{}", state.to_string());
+                println!("{}", state);
             }
         }
 
-        let reward = if winner == Piece::Draw {
-            0.5
+        self.end_episode();
+        for player in self.player.iter_mut() {
+            player.observe_end(&self.board, winner, &self.params);
+        }
+        self.remember(&state_history, &action_history);
+        for (player, buffer) in self.player.iter_mut().zip(self.replay.iter_mut()) {
+            if let Some(buffer) = buffer {
+                player.replay(buffer, &mut self.rng, &self.params);
+            }
+        }
+        if !self.paused {
+            self.schedules.advance(Tick::Game, &mut self.params);
         }
-        else {
-            -0.5
-        };
-        self.backprop(state_history, action_history, reward);
 
         winner
     }
 
 
-    /// Lets the current agent take its turn.
-    /// Returns the winning piece and action taken.
-    fn agent_turn(&mut self) -> (Piece, Action) {
+    /// Lets the player on the play take its turn.
+    /// Returns the winning piece and action taken, None if the player gave up.
+    fn take_turn(&mut self) -> Option<(Piece, Action)> {
         // Clone current board
         let prev_state = self.board.clone();
+        let index = prev_state.on_play().to_index();
+
+        // The player's last move led here, so it is observed before the player chooses again.
+        if let Some((before, action, after)) = self.waiting[index].take() {
+            let reward = self.params.rewards.transition(&before, &prev_state, action.player, self.params.gamma);
+            self.player[index].observe(&before, &action, reward, &after, &mut self.rng, &self.params);
+        }
 
         // Find the current player and get them to choose an action.
-        let player = &mut self.player[self.board.on_play().to_index()];
-        let action = player.choose_action(prev_state.clone(), &mut self.rng, self.epsilon);
-        let (winner, reward) = Self::update_board(&mut self.board, &action);
-        let state = self.board.clone();
+        let player = &mut self.player[index];
+        let action = player.choose_action(&prev_state, &mut self.rng, &self.params)?;
+        self.board.play_mut(&action);
+        let winner = self.board.check_winner();
+
+        // Let the agent learn from a move that ended the game, any other waits for the player's next turn.
+        if winner != Piece::Empty {
+            let reward = self.params.rewards.transition(&prev_state, &self.board, action.player, self.params.gamma);
+            player.observe(&prev_state, &action, reward, &self.board, &mut self.rng, &self.params);
+        }
+        else {
+            self.waiting[index] = Some((prev_state, action, self.board.clone()));
+        }
+        if !self.paused {
+            self.schedules.advance(Tick::Step, &mut self.params);
+        }
 
-        // Update the agent's Q-table
-        player.update_q(prev_state, state, &action, reward, self.eta, self.gamma);
+        Some((winner, action))
+    }
 
-        (winner, action)
+    /// Takes a turn in a game with a human, showing the agent's move and the Q values behind it.
+    fn shown_turn(&mut self) -> Option<(Piece, Action)> {
+        let prev_state = self.board.clone();
+        let player = &self.player[prev_state.on_play().to_index()];
+        if player.is_human() {
+            return self.take_turn();
+        }
+        let mut q_values = player.q_values(&prev_state);
+        let result = self.take_turn()?;
+
+        println!("{} plays {}", result.1.player, human::describe(&prev_state, &result.1));
+        human::print_q_values(&prev_state, &mut q_values);
+        println!();
+        Some(result)
     }
 
-    /// Uses the final played turn of the losing player to update its Q-table.
-    fn backprop(&mut self, state_history: Vec<State>, action_history: Vec<Action>, reward: Value) {
-        // Get the correct 2 states.
-        let mut state_iter = state_history.into_iter().rev();
-        state_iter.next();
-        let state = state_iter.next().unwrap();
-        let prev_state = state_iter.next().unwrap();
-
-        // Get the correct action that was between them.
-        let mut action_iter = action_history.iter().rev();
-        let action = action_iter.nth(2).unwrap();
-        let player = &mut self.player[action.player.to_index()];
-        player.update_q(prev_state, state, action, reward, self.eta, self.gamma)
+    /// Gives the player who did not make the final move its terminal update.
+    /// The final mover already saw the result in `take_turn`.
+    /// The other player's last move is observed as it was played, then again leading straight
+    /// to the final board, paid the loss or draw reward, in place of the update the agent was holding.
+    fn end_episode(&mut self) {
+        for index in 0..self.waiting.len() {
+            let Some((before, action, after)) = self.waiting[index].take() else { continue };
+            let reward = self.params.rewards.transition(&before, &self.board, action.player, self.params.gamma);
+            let player = &mut self.player[index];
+            player.observe(&before, &action, reward, &after, &mut self.rng, &self.params);
+            player.observe(&before, &action, reward, &self.board, &mut self.rng, &self.params);
+        }
     }
 
-    /// Updates the board state in-place.
-    /// Returns a winner and the reward value for that action.
-    fn update_board(board: &mut State, action: &Action) -> (Piece, Value) {
-        // Update board and clone it using the chosen action.
-        board.play_mut(action);
-        let winner = board.check_winner();
-        let reward = {
-            if winner == action.player {
-                1.0
-            }
-            else if winner == Piece::Draw {
-                0.5
-            }
-            else {
-                0.0
-            }
-        };
-        (winner, reward)
+    /// Stores every move of the game in the replay buffer of the player who made it.
+    /// The next board is the one that player saw on its next turn, or the final one.
+    fn remember(&mut self, state_history: &[State], action_history: &[Action]) {
+        let last = state_history.len() - 1;
+        for (i, action) in action_history.iter().enumerate() {
+            let Some(buffer) = self.replay[action.player.to_index()].as_mut() else { continue };
+            let state = &state_history[i];
+            let next = &state_history[(i + 2).min(last)];
+            let terminal = next.check_winner() != Piece::Empty;
+            let reward = self.params.rewards.transition(state, next, action.player, self.params.gamma);
+            buffer.push(Transition { state: state.clone(), action: *action, reward, next: next.clone(), terminal });
+        }
     }
 
 }
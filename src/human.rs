@@ -0,0 +1,147 @@
+/*
+Terminal input and output for a human playing against an agent.
+Moves are typed as a row and column pair (`2 3` or `2,3`) or a single cell number,
+counting from 1. Under gravity rules a single number names the column.
+*/
+
+use std::any::Any;
+use std::io::{self, BufRead, Write};
+use bracket_lib::random::RandomNumberGenerator;
+
+use crate::agent::{Agent, Params};
+use crate::piece::Piece;
+use crate::q_agent::Value;
+use crate::state::*;
+use crate::vector::Vector2;
+
+/// A seat played from the terminal.
+pub struct HumanAgent {
+    player: Piece,
+}
+
+impl HumanAgent {
+    pub fn new(player: Piece) -> HumanAgent {
+        HumanAgent { player }
+    }
+}
+
+impl Agent for HumanAgent {
+    fn player(&self) -> Piece {
+        self.player
+    }
+
+    /// Asks for a move, giving up the game if stdin is closed.
+    fn choose_action(&mut self, state: &State, _rng: &mut RandomNumberGenerator, _params: &Params) -> Option<Action> {
+        let action = read_action(state, self.player);
+        if action.is_none() {
+            println!();
+        }
+        action
+    }
+
+    fn is_human(&self) -> bool {
+        true
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Draws the board with row and column numbers and cell numbers on the empty cells.
+pub fn render(state: &State) -> String {
+    let size = state.rules().board_size;
+    let width = (size.x * size.y).to_string().len().max(1);
+    let mut string = String::from("   ");
+    for j in 0..size.y {
+        string.push_str(&format!(" {:^width$} ", j + 1, width = width));
+    }
+    string.push('\n');
+    for i in 0..size.x {
+        string.push_str(&format!("{:>2} ", i + 1));
+        for j in 0..size.y {
+            let cell = match state.get_piece(&Vector2::new(i, j)) {
+                Piece::P1 => "X".to_string(),
+                Piece::P2 => "O".to_string(),
+                _ if state.rules().gravity => "_".to_string(),
+                _ => (i * size.y + j + 1).to_string(),
+            };
+            string.push_str(&format!("[{:^width$}]", cell, width = width));
+        }
+        string.push('\n');
+    }
+    string
+}
+
+/// Describes an action in the same numbering the human types.
+pub fn describe(state: &State, action: &Action) -> String {
+    if state.rules().gravity {
+        format!("column {}", action.position.y + 1)
+    }
+    else {
+        format!("row {}, column {}", action.position.x + 1, action.position.y + 1)
+    }
+}
+
+/// Prints the Q-value of each legal action, best first.
+pub fn print_q_values(state: &State, q_values: &mut [(Action, Value)]) {
+    q_values.sort_by(|a, b| b.1.total_cmp(&a.1));
+    for (action, value) in q_values.iter() {
+        println!("    {:<22} Q = {:.4}", describe(state, action), value);
+    }
+}
+
+/// Asks the human for a move until they type a legal one.
+/// Returns None if stdin is closed.
+pub fn read_action(state: &State, player: Piece) -> Option<Action> {
+    println!("{}", render(state));
+    let stdin = io::stdin();
+    loop {
+        print!("{} to play, enter a move: ", player);
+        let _ = io::stdout().flush();
+
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) | Err(_) => return None,
+            Ok(_) => (),
+        }
+        match parse_action(state, player, &line) {
+            Ok(action) => return Some(action),
+            Err(message) => println!("{}", message),
+        }
+    }
+}
+
+/// Turns typed input into a legal action on this state.
+fn parse_action(state: &State, player: Piece, input: &str) -> Result<Action, String> {
+    let rules = state.rules();
+    let size = rules.board_size;
+    let numbers = input
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse::<i32>().map_err(|_| format!("'{}' is not a number.", s)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let position = match numbers.as_slice() {
+        [column] if rules.gravity => Vector2::new(0, column - 1),
+        [cell] => {
+            if *cell < 1 || *cell > size.x * size.y {
+                return Err(format!("Cell {} is off the board, pick 1 to {}.", cell, size.x * size.y));
+            }
+            Vector2::new((cell - 1) / size.y, (cell - 1) % size.y)
+        },
+        [row, column] => Vector2::new(row - 1, column - 1),
+        _ => return Err("Enter a row and column like '2 3', or a single cell number.".to_string()),
+    };
+
+    let off_board = position.y < 0 || position.y >= size.y ||
+        (!rules.gravity && (position.x < 0 || position.x >= size.x));
+    if off_board {
+        return Err(format!("That is off the board, rows go 1 to {} and columns 1 to {}.", size.x, size.y));
+    }
+    let action = Action { player, position };
+    if !state.is_legal(&action) {
+        return Err(format!("{} is already taken.", describe(state, &action)));
+    }
+    Ok(action)
+}
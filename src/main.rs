@@ -4,74 +4,355 @@ Artificial Intelligence
 2022-11-23
 */
 
+mod agent;
 mod q_agent;
+mod mc_agent;
+mod afterstate_agent;
+mod dyna_agent;
 mod state;
 mod vector;
 mod game;
 mod piece;
 mod plotter;
+mod rules;
+mod symmetry;
+mod persist;
+mod human;
+mod solver;
+mod metrics;
+mod reward;
+mod exploration;
+mod schedule;
+mod replay;
+
+use q_agent::{UpdateRule, Trace, StepSize};
+use mc_agent::Visits;
+use dyna_agent::Planning;
+use reward::RewardScheme;
+use exploration::Exploration;
+use schedule::{Schedule, Scheduled, Schedules, Tick};
+use replay::{ReplayBuffer, Sampling};
 
 fn main() {
-    experiment();
+    match std::env::args().nth(1).as_deref() {
+        Some("bench") => benchmark(),
+        Some("play") => play_human(),
+        Some("evaluate") => evaluate(),
+        Some(name) => match Variant::find(name) {
+            Some(variant) => experiment(variant),
+            None => {
+                let names: Vec<_> = Variant::all().iter().map(|v| v.name).collect();
+                println!("Unknown variant '{}', pick one of: {}", name, names.join(", "));
+            },
+        },
+        None => experiment(Variant::baseline()),
+    }
+}
+
+/// The learning settings that differ between runs of `experiment`,
+/// picked by name on the command line, e.g. `cargo run --release -- sarsa`.
+#[derive(Clone, Copy)]
+struct Variant {
+    name: &'static str,
+    update_rule: UpdateRule,
+    double_q: bool,
+    trace: Option<Trace>,
+    learner: Learner,
+    opponent: Opponent,
+    rewards: RewardScheme,
+    exploration: Exploration,
+    /// How epsilon changes over training, None for the default decrement every 10 epochs.
+    epsilon: Option<Scheduled>,
+    /// How the softmax temperature changes over training, None for the default step every 10 epochs.
+    temperature: Option<Scheduled>,
+    step_size: StepSize,
+    initial_q: q_agent::Value,
+    /// Replays remembered moves after every game, drawn this way.
+    replay: Option<Sampling>,
+}
+
+/// Who the learning agent plays against.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Opponent {
+    /// A random player in the other seat.
+    Random,
+    /// A second learner of the same kind in the other seat.
+    SelfPlay,
+    /// A second `QLearningAgent` learning in the same table, each seat bootstrapping from the other's replies.
+    SharedSelfPlay,
+}
+
+/// Which kind of agent `experiment` trains.
+#[derive(Clone, Copy)]
+enum Learner {
+    /// `QLearningAgent`, configured by the rest of the variant.
+    TemporalDifference,
+    /// `MonteCarloAgent`, learning from whole games.
+    MonteCarlo(Visits),
+    /// `AfterstateAgent`, learning the value of the board after each move.
+    Afterstate,
+    /// `DynaAgent`, the `QLearningAgent` of the variant planning this many simulated updates per move.
+    Dyna { steps: usize, planning: Planning },
+}
+
+impl Variant {
+    fn baseline() -> Variant {
+        Variant { name: "baseline", update_rule: UpdateRule::QLearning, double_q: false, trace: None,
+            learner: Learner::TemporalDifference, opponent: Opponent::Random,
+            rewards: RewardScheme::default(), exploration: Exploration::EpsilonGreedy,
+            epsilon: None, temperature: None, step_size: StepSize::Fixed, initial_q: 0., replay: None }
+    }
+
+    fn all() -> Vec<Variant> {
+        let baseline = Variant::baseline();
+        vec![
+            baseline,
+            Variant { name: "sarsa", update_rule: UpdateRule::Sarsa, ..baseline },
+            Variant { name: "expected-sarsa", update_rule: UpdateRule::ExpectedSarsa, ..baseline },
+            Variant { name: "double-q", double_q: true, ..baseline },
+            Variant { name: "negamax", update_rule: UpdateRule::Negamax, rewards: RewardScheme::new(1., -1., 0.), ..baseline },
+            Variant { name: "q-lambda", trace: Some(Trace::Replacing), ..baseline },
+            Variant { name: "sarsa-lambda", update_rule: UpdateRule::Sarsa, trace: Some(Trace::Accumulating), ..baseline },
+            Variant { name: "mc-first-visit", learner: Learner::MonteCarlo(Visits::First), ..baseline },
+            Variant { name: "mc-every-visit", learner: Learner::MonteCarlo(Visits::Every), ..baseline },
+            Variant { name: "afterstate", learner: Learner::Afterstate, ..baseline },
+            Variant { name: "dyna-q", learner: Learner::Dyna { steps: 10, planning: Planning::Uniform }, ..baseline },
+            Variant { name: "prioritized-sweeping", learner: Learner::Dyna { steps: 10,
+                planning: Planning::Sweeping { threshold: 1e-2 } }, ..baseline },
+            Variant { name: "self-play", opponent: Opponent::SelfPlay, ..baseline },
+            Variant { name: "self-play-shared", opponent: Opponent::SharedSelfPlay, update_rule: UpdateRule::Negamax,
+                rewards: RewardScheme::new(1., -1., 0.), ..baseline },
+            Variant { name: "softmax", exploration: Exploration::Softmax, ..baseline },
+            Variant { name: "ucb", exploration: Exploration::Ucb { c: 1. }, ..baseline },
+            Variant { name: "mc-softmax", learner: Learner::MonteCarlo(Visits::First), exploration: Exploration::Softmax, ..baseline },
+            Variant { name: "afterstate-softmax", learner: Learner::Afterstate, exploration: Exploration::Softmax, ..baseline },
+            Variant { name: "epsilon-linear", epsilon: Some(Scheduled::new(
+                Schedule::Linear { start: 0.1, end: 0., ticks: 100 }, Tick::Epoch)), ..baseline },
+            Variant { name: "epsilon-exponential", epsilon: Some(Scheduled::new(
+                Schedule::Exponential { start: 0.1, rate: 0.99999, min: 0.001 }, Tick::Game)), ..baseline },
+            Variant { name: "epsilon-cosine", epsilon: Some(Scheduled::new(
+                Schedule::Cosine { start: 0.1, end: 0., ticks: 100 }, Tick::Epoch)), ..baseline },
+            Variant { name: "epsilon-inverse", epsilon: Some(Scheduled::new(
+                Schedule::InverseTime { start: 0.1, scale: 100_000. }, Tick::Step)), ..baseline },
+            Variant { name: "epsilon-visits", epsilon: Some(Scheduled::new(
+                Schedule::InverseVisits { start: 0.1, scale: 1000. }, Tick::Epoch)), ..baseline },
+            Variant { name: "softmax-visits", exploration: Exploration::Softmax, temperature: Some(Scheduled::new(
+                Schedule::InverseVisits { start: 0.2, scale: 1000. }, Tick::Epoch)), ..baseline },
+            Variant { name: "inverse-count", step_size: StepSize::InverseCount { floor: 0.01 }, ..baseline },
+            Variant { name: "polynomial", step_size: StepSize::Polynomial { omega: 0.6, floor: 0.01 }, ..baseline },
+            Variant { name: "optimistic", initial_q: 1., ..baseline },
+            Variant { name: "replay", replay: Some(Sampling::Uniform), ..baseline },
+            Variant { name: "prioritized-replay", replay: Some(Sampling::Prioritized { alpha: 0.6, beta: 0.4 }), ..baseline },
+            Variant { name: "move-cost", rewards: RewardScheme::default().with_per_move(-0.05), ..baseline },
+            Variant { name: "shaped", rewards: RewardScheme::default().with_shaping(reward::open_lines), ..baseline },
+        ]
+    }
+
+    fn find(name: &str) -> Option<Variant> {
+        Variant::all().into_iter().find(|v| v.name == name)
+    }
+}
+
+/// Builds the `QLearningAgent` a variant describes.
+fn q_learner(variant: Variant, rules: rules::Rules, player: piece::Piece, symmetry: bool) -> q_agent::QLearningAgent {
+    q_agent::QLearningAgent::new(rules, player)
+        .with_symmetry(symmetry)
+        .with_update_rule(variant.update_rule)
+        .with_double_q(variant.double_q)
+        .with_traces(variant.trace)
+        .with_exploration(variant.exploration)
+        .with_step_size(variant.step_size)
+        .with_initial_q(variant.initial_q)
+}
+
+/// Builds the learning agent a variant describes.
+fn learner(variant: Variant, rules: rules::Rules, player: piece::Piece, symmetry: bool) -> Box<dyn agent::Agent> {
+    use mc_agent::MonteCarloAgent;
+    use afterstate_agent::AfterstateAgent;
+    use dyna_agent::DynaAgent;
+
+    match variant.learner {
+        Learner::MonteCarlo(visits) => Box::new(MonteCarloAgent::new(rules, player)
+            .with_symmetry(symmetry)
+            .with_visits(visits)
+            .with_sample_average(true)
+            .with_exploration(variant.exploration)),
+        Learner::Afterstate => Box::new(AfterstateAgent::new(rules, player)
+            .with_symmetry(symmetry)
+            .with_exploration(variant.exploration)),
+        Learner::Dyna { steps, planning } => Box::new(DynaAgent::new(q_learner(variant, rules, player, symmetry), steps)
+            .with_planning(planning)),
+        Learner::TemporalDifference => Box::new(q_learner(variant, rules, player, symmetry)),
+    }
 }
 
-fn experiment() {
+fn experiment(variant: Variant) {
     use piece::Piece;
+    use agent::RandomAgent;
+    use q_agent::QLearningAgent;
 
     // Initial specifications
-    let board_size = vector::Vector2::new(3, 3);
+    // Swap in rules::Rules::connect_four() for the gravity variant.
+    let rules = rules::Rules::tic_tac_toe();
+    let symmetry = true;
     let eta = 0.1;
     let gamma = 0.9;
+    let lambda = 0.8;
     
-    // For changing epsilon
+    // Epsilon drops by delta every m epochs.
     let epsilon = 0.1;
     let delta = 0.01;
     let m = 10;
-    
-    // Create the board and agents.
-    let mut game = game::Game::new(board_size, epsilon, eta, gamma);
+    let temperature = 0.2;
 
     // Define training times.
     let epochs = 100;
-    let epoch_games = usize::pow(2, 12);
+    // Planning does most of the learning in simulation, so Dyna needs far fewer real games.
+    let epoch_games = match variant.learner {
+        Learner::Dyna { .. } => usize::pow(2, 9),
+        _ => usize::pow(2, 12),
+    };
     let test_games = 10;
+    let seat_games = 100;
+    let replay_capacity = 10_000;
+    let replay_batch = 32;
+
+    // How epsilon, eta and the softmax temperature change over training.
+    let decrement = Schedule::Decrement { start: epsilon, delta, every: m, min: 0. };
+    let cooling = Schedule::Step { start: temperature, factor: 0.8, every: 10 };
+    let schedules = Schedules {
+        epsilon: Some(variant.epsilon.unwrap_or(Scheduled::new(decrement, Tick::Epoch))),
+        eta: Some(Scheduled::new(Schedule::Constant(eta), Tick::Epoch)),
+        temperature: Some(variant.temperature.unwrap_or(Scheduled::new(cooling, Tick::Epoch))),
+    };
+    
+    // Who is playing, the other seat plays at random unless both seats learn.
+    let agent_player = Piece::P2;
+    let (p1, p2): (Box<dyn agent::Agent>, Box<dyn agent::Agent>) = match variant.opponent {
+        Opponent::Random => {
+            let agent = learner(variant, rules, agent_player, symmetry);
+            let opponent = Box::new(RandomAgent::new(agent_player.opponent()));
+            match agent_player {
+                Piece::P1 => (agent, opponent),
+                _ => (opponent, agent),
+            }
+        },
+        Opponent::SelfPlay => (learner(variant, rules, Piece::P1, symmetry), learner(variant, rules, Piece::P2, symmetry)),
+        Opponent::SharedSelfPlay => {
+            let first = q_learner(variant, rules, Piece::P1, symmetry);
+            let second = q_learner(variant, rules, Piece::P2, symmetry)
+                .with_shared_table(&first);
+            (Box::new(first), Box::new(second))
+        },
+    };
+
+    // Create the board and agents.
+    let mut game = game::Game::new(rules, p1, p2, epsilon, eta, gamma, lambda);
+    game.params.rewards = variant.rewards;
+    game.set_schedules(schedules);
+
+    // Learning seats replay a mini-batch of their remembered moves after every game.
+    if let Some(sampling) = variant.replay {
+        let seats = if variant.opponent == Opponent::Random { vec![agent_player] } else { vec![Piece::P1, Piece::P2] };
+        for seat in seats {
+            game.set_replay(seat, ReplayBuffer::new(replay_capacity, replay_batch).with_sampling(sampling));
+        }
+    }
+
+    // Continue training a saved agent instead of starting from scratch.
+    let load_path: Option<&str> = None;
+    if let Some(path) = load_path {
+        match QLearningAgent::load(path) {
+            Ok(agent) if agent.rules() == rules => {
+                game.set_agent(Box::new(agent));
+            },
+            Ok(_) => println!("Could not load {}: trained on different rules", path),
+            Err(error) => println!("Could not load {}: {}", path, error),
+        }
+    }
+
     let mut test_data = Vec::<plotter::QResult>::with_capacity(epochs);
+
+    // Compare the greedy policy to perfect play after each epoch, only feasible on small boards.
+    let measure_quality = metrics::feasible(rules);
+    let mut solver = solver::Solver::new();
+    let mut quality_data = Vec::<metrics::Quality>::with_capacity(epochs);
+    let mut param_data = Vec::<agent::Params>::with_capacity(epochs);
  
-    // Who is playing and do we print the games.
+    // Do we print the games.
     let print = false;
-    let agent_player = Piece::P2;
-    let human_player = Piece::Empty;
 
-    for e in 0..epochs {
+    for _ in 0..epochs {
+        param_data.push(game.params);
+
         // Training
-        let _train_result = epoch(&mut game, epoch_games, print, agent_player, Piece::Empty);
+        let _train_result = epoch(&mut game, epoch_games, print);
 
-        // Testing
-        let test_result = epoch(&mut game, test_games, print, agent_player, human_player);
+        // Testing, without moving the schedules along.
+        game.pause_schedules(true);
+        let mut test_result = epoch(&mut game, test_games, print);
+        if variant.opponent != Opponent::Random {
+            // Test each learning seat against a random player in the other one.
+            for seat in [Piece::P1, Piece::P2] {
+                let trained = game.set_agent(Box::new(RandomAgent::new(seat.opponent())));
+                let seat_result = epoch(&mut game, seat_games, print);
+                test_result.seats[seat.to_index()] = plotter::SeatResult::from_games(&seat_result, seat);
+                game.set_agent(trained);
+            }
+        }
+        game.pause_schedules(false);
         test_data.push(test_result);
 
-        // Check if we should decrement epsilon.
-        if e % m == 0 && game.epsilon > 0. {
-            let temp = game.epsilon - delta;
-            if temp <= 0. { game.epsilon = 0.; }
-            else { game.epsilon = temp; }
+        // Policy quality
+        if measure_quality {
+            if let Some(quality) = metrics::measure(game.agent(agent_player), rules, &mut solver) {
+                quality_data.push(quality);
+            }
         }
+
+        game.end_epoch();
     }
 
-    let file_name = format!("{}_{}", agent_player.to_string(), epochs);
-    let title = format!("agent: {}, epochs: {}", agent_player.to_string(), epochs);
-    let _error = plotter::visualize(test_data, file_name, title);
+    let mut file_name = format!("{}_{}", agent_player, epochs);
+    if variant.name != Variant::baseline().name {
+        file_name = format!("{} - {}", file_name, variant.name);
+    }
+    let title = format!("agent: {}, epochs: {}, {}", agent_player, epochs, variant.name);
+    if variant.opponent != Opponent::Random {
+        if let Some(last) = test_data.last() {
+            last.analyze();
+        }
+        let _error = plotter::visualize_seats(&test_data, format!("{}_seats", file_name), title.clone());
+    }
+    let _error = plotter::visualize(test_data, file_name.clone(), title.clone());
+    let _error = plotter::visualize_params(&param_data, format!("{}_params", file_name), title.clone());
+    if let Some(last) = quality_data.last() {
+        println!("Agrees with perfect play in {:.1}% of {} positions, worst case {}, exploitability {}",
+            last.agreement * 100., last.positions, last.worst_case, last.exploitability());
+        let _error = plotter::visualize_quality(quality_data, format!("{}_quality", file_name), title);
+    }
+
+    // Save the trained agent in both formats.
+    for extension in ["bin", "json"] {
+        let path = format!("./agents/{}.{}", file_name, extension);
+        let agent = game.agent(agent_player).as_any();
+        let agent = agent.downcast_ref::<QLearningAgent>()
+            .or_else(|| agent.downcast_ref::<dyna_agent::DynaAgent>().map(|dyna| dyna.agent()));
+        if let Err(error) = agent.map_or(Ok(()), |agent| agent.save(&path)) {
+            println!("Could not save {}: {}", path, error);
+        }
+    }
     
 }
 
-fn epoch(game: &mut game::Game, to_play: usize, print: bool, agent_player: piece::Piece, human_player: piece::Piece) -> plotter::QResult {
+fn epoch(game: &mut game::Game, to_play: usize, print: bool) -> plotter::QResult {
     use crate::piece::Piece;
     let mut result = plotter::QResult::new(to_play);
 
     // Play the games.
     for i in 0..to_play {
         if print { println!("---------- Game {} ----------", i); }
-        let winner = game.play(print, agent_player, human_player);
+        let winner = game.play(print);
         match winner {
             Piece::P1 => result.p1_win += 1,
             Piece::P2 => result.p2_win += 1,
@@ -83,3 +364,143 @@ fn epoch(game: &mut game::Game, to_play: usize, print: bool, agent_player: piece
 
     result
 }
+
+/// Measures how many training games per second the game loop can play,
+/// against random play alone as a baseline for the cost of learning,
+/// and how fast the Q-table hasher is against the standard SipHash on the same keys.
+/// Run with `cargo run --release -- bench`.
+fn benchmark() {
+    use piece::Piece;
+    use std::collections::hash_map::RandomState;
+    use std::time::Instant;
+
+    let epsilon = 0.1;
+    let eta = 0.1;
+    let gamma = 0.9;
+    let lambda = 0.;
+    let to_play = usize::pow(2, 18);
+    let to_look_up = usize::pow(2, 20);
+
+    for (name, rules) in [("tic-tac-toe", rules::Rules::tic_tac_toe()), ("connect four", rules::Rules::connect_four())] {
+        let seats: [(&str, Box<dyn agent::Agent>); 2] = [
+            ("random", Box::new(agent::RandomAgent::new(Piece::P2))),
+            ("q-learning", Box::new(q_agent::QLearningAgent::new(rules, Piece::P2))),
+        ];
+        for (seat, p2) in seats {
+            let p1 = Box::new(agent::RandomAgent::new(Piece::P1));
+            let mut game = game::Game::new(rules, p1, p2, epsilon, eta, gamma, lambda);
+            let start = Instant::now();
+            epoch(&mut game, to_play, false);
+            let seconds = start.elapsed().as_secs_f64();
+            println!("{:>12}: {} games against {} in {:.2}s ({:.0} games/s)",
+                name, to_play, seat, seconds, to_play as f64 / seconds);
+        }
+
+        let keys = sample_keys(rules, to_look_up);
+        let sip = table_updates::<RandomState>(&keys);
+        let key_hash = table_updates::<state::KeyHash>(&keys);
+        println!("{:>12}: {:.0} table updates/s with SipHash, {:.0} with KeyHash ({:.2}x)",
+            name, sip, key_hash, key_hash / sip);
+    }
+}
+
+/// Pairs of a board and a move from random games, as a Q-table sees them.
+fn sample_keys(rules: rules::Rules, count: usize) -> Vec<(state::State, state::Action)> {
+    let mut rng = bracket_lib::random::RandomNumberGenerator::new();
+    let mut state = state::State::new(rules);
+    let mut keys = Vec::with_capacity(count);
+    while keys.len() < count {
+        if state.check_winner() != piece::Piece::Empty {
+            state.reset();
+        }
+        let actions = state.legal_actions(state.on_play());
+        let action = actions[rng.range(0, actions.len())];
+        keys.push((state.clone(), action));
+        state.play_mut(&action);
+    }
+    keys
+}
+
+/// Counts a visit of every key in a table hashed with S, then reads every count back.
+/// Returns the updates and reads per second.
+fn table_updates<S: std::hash::BuildHasher + Default>(keys: &[(state::State, state::Action)]) -> f64 {
+    let start = std::time::Instant::now();
+    let mut table: std::collections::HashMap<_, (q_agent::Value, q_agent::Frequency), S> = Default::default();
+    for k in keys {
+        table.entry(k.clone()).or_insert((0., 0)).1 += 1;
+    }
+    let visits: q_agent::Frequency = keys.iter().map(|k| table[k].1).sum();
+    std::hint::black_box(visits);
+    2. * keys.len() as f64 / start.elapsed().as_secs_f64()
+}
+
+/// Plays the saved agent greedily against a perfect opponent, from the seat it was trained in,
+/// as the experiment only saves that seat. A converged agent should never lose.
+/// Run with `cargo run --release -- evaluate`.
+fn evaluate() {
+    use piece::Piece;
+    use q_agent::QLearningAgent;
+    use solver::MinimaxAgent;
+
+    let rules = rules::Rules::tic_tac_toe();
+    let agent_player = Piece::P2;
+    let path = format!("./agents/{}_100.bin", agent_player);
+    let games = 1000;
+    let blunder = 0.;
+
+    let agent = match QLearningAgent::load(&path) {
+        Ok(agent) if agent.rules() == rules => agent,
+        _ => {
+            println!("No saved agent at {}, run the experiment first.", path);
+            return;
+        },
+    };
+    let opponent = MinimaxAgent::new(agent_player.opponent(), blunder);
+    let (p1, p2): (Box<dyn agent::Agent>, Box<dyn agent::Agent>) = match agent_player {
+        Piece::P1 => (Box::new(agent), Box::new(opponent)),
+        _ => (Box::new(opponent), Box::new(agent)),
+    };
+
+    let value = solver::Solver::new().solve(&state::State::new(rules)).value;
+    println!("Value of the opening for {} with perfect play: {}", Piece::P1, value);
+
+    // No exploration and no learning while being evaluated.
+    let mut game = game::Game::new(rules, p1, p2, 0., 0., 0.9, 0.);
+    println!("{} against a perfect {}:", agent_player, agent_player.opponent());
+    epoch(&mut game, games, false).analyze();
+}
+
+/// Plays against a saved agent from the terminal, training a fresh one first if none is saved.
+/// Run with `cargo run --release -- play`.
+fn play_human() {
+    use piece::Piece;
+    use q_agent::QLearningAgent;
+
+    let rules = rules::Rules::tic_tac_toe();
+    let agent_player = Piece::P2;
+    let human_player = Piece::P1;
+    let path = format!("./agents/{}_100.bin", agent_player);
+
+    let agent = match QLearningAgent::load(&path) {
+        Ok(agent) if agent.rules() == rules => agent,
+        _ => QLearningAgent::new(rules, agent_player).with_symmetry(true),
+    };
+    let trained = !agent.is_empty();
+    let opponent = Box::new(agent::RandomAgent::new(human_player));
+    let mut game = game::Game::new(rules, opponent, Box::new(agent), 0.1, 0.1, 0.9, 0.);
+    if !trained {
+        println!("No saved agent at {}, training one.", path);
+        epoch(&mut game, usize::pow(2, 16), false);
+    }
+
+    // Always play the best known move against the human.
+    game.params.epsilon = 0.;
+    game.set_agent(Box::new(human::HumanAgent::new(human_player)));
+    println!("You are {}. Enter moves as 'row column' or a cell number, Ctrl-D to quit.", human_player);
+    // Closing the input gives up the current game and ends the session.
+    while game.play(false) != Piece::Empty {
+        game.reset();
+        println!();
+        println!("---------- New game ----------");
+    }
+}
@@ -0,0 +1,165 @@
+/*
+Monte Carlo control.
+The agent remembers every move it made in a game and, once the game is over,
+moves each of them toward the return that actually followed instead of bootstrapping
+from its own estimates like the TD agents do.
+*/
+
+use std::any::Any;
+use std::collections::HashSet;
+use bracket_lib::random::RandomNumberGenerator;
+
+use crate::agent::{Agent, Params};
+use crate::exploration::{self, Exploration};
+use crate::piece::Piece;
+use crate::q_agent::{QTable, Value, Frequency};
+use crate::rules::Rules;
+use crate::state::*;
+use crate::symmetry::Symmetries;
+
+/// Which visits of a state/action pair in one game count toward its average.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Visits {
+    /// Only the first time the pair is played in a game.
+    First,
+    /// Every time the pair is played in a game.
+    Every,
+}
+
+pub struct MonteCarloAgent {
+    q_table:    QTable,
+    rules:      Rules,
+    player:     Piece,
+    symmetries: Symmetries,
+    exploration: Exploration,
+    visits:     Visits,
+    // Step with 1/N from the visit counts instead of eta.
+    sample_average: bool,
+    // Keys of the moves played so far this game, in order, with the reward each one got.
+    episode:    Vec<((State, Action), Value)>,
+}
+
+impl MonteCarloAgent {
+    pub fn new(rules: Rules, player: Piece) -> MonteCarloAgent {
+        let symmetries = Symmetries::new(rules, false);
+        MonteCarloAgent { q_table: QTable::default(), rules, player, symmetries, exploration: Exploration::EpsilonGreedy,
+            visits: Visits::First, sample_average: false, episode: Vec::new() }
+    }
+
+    /// Chooses between first-visit and every-visit updates.
+    /// Positions never repeat within a game of tic-tac-toe or Connect Four, so both agree there.
+    pub fn with_visits(mut self, visits: Visits) -> MonteCarloAgent {
+        self.visits = visits;
+        self
+    }
+
+    /// Averages every return seen for a pair instead of stepping by eta.
+    pub fn with_sample_average(mut self, enabled: bool) -> MonteCarloAgent {
+        self.sample_average = enabled;
+        self
+    }
+
+    /// Stores every state under its canonical rotation/reflection.
+    pub fn with_symmetry(mut self, enabled: bool) -> MonteCarloAgent {
+        self.symmetries = Symmetries::new(self.rules, enabled);
+        self
+    }
+
+    /// Chooses how moves are picked while learning.
+    pub fn with_exploration(mut self, exploration: Exploration) -> MonteCarloAgent {
+        self.exploration = exploration;
+        self
+    }
+
+    /// Moves every pair of the finished game toward its discounted return.
+    fn learn_episode(&mut self, params: &Params) {
+        let episode = std::mem::take(&mut self.episode);
+        // The return of each move is its reward plus the discounted return of our next move.
+        let mut returns = Vec::with_capacity(episode.len());
+        let mut g = 0.;
+        for (_, reward) in episode.iter().rev() {
+            g = reward + params.gamma * g;
+            returns.push(g);
+        }
+        returns.reverse();
+
+        let mut seen = HashSet::new();
+        for ((k, _), g) in episode.into_iter().zip(returns) {
+            if self.visits == Visits::First && !seen.insert(k.clone()) {
+                continue;
+            }
+            let entry = self.q_table.entry(k).or_insert((0., 0));
+            entry.1 += 1;
+            let step = if self.sample_average { 1. / entry.1 as Value } else { params.eta };
+            entry.0 += step * (g - entry.0);
+        }
+    }
+
+    /// The Q-table key for a state and action, on the canonical board.
+    fn key(&self, state: &State, action: &Action) -> (State, Action) {
+        self.symmetries.key(state, action)
+    }
+
+    /// Looks up a single Q value, 0 if it has never been updated.
+    fn q_value(&self, state: &State, action: &Action) -> Value {
+        self.q_table.get(&self.key(state, action)).map_or(0., |(q, _frq)| *q)
+    }
+
+    /// How often the return of each legal action has been averaged in, in the order of `q_values`.
+    fn counts(&self, state: &State) -> Vec<Frequency> {
+        state.legal_actions(self.player).iter()
+            .map(|action| self.q_table.get(&self.key(state, action)).map_or(0, |(_q, frq)| *frq))
+            .collect()
+    }
+}
+
+impl Agent for MonteCarloAgent {
+    fn player(&self) -> Piece {
+        self.player
+    }
+
+    /// Explores around the averaged returns and remembers the move.
+    fn choose_action(&mut self, state: &State, rng: &mut RandomNumberGenerator, params: &Params) -> Option<Action> {
+        let q_values = self.q_values(state);
+        if q_values.is_empty() {
+            panic!("No legal actions");
+        }
+        let greedy = exploration::greedy(&q_values, rng);
+        let action = self.exploration.choose(&q_values, &self.counts(state), greedy, rng, params);
+        self.episode.push((self.key(state, &action), 0.));
+        Some(action)
+    }
+
+    /// Keeps the reward of our last move. A second observation at the end of the game replaces it.
+    fn observe(&mut self, _prev_state: &State, _action: &Action, reward: Value, _state: &State,
+        _rng: &mut RandomNumberGenerator, _params: &Params) {
+        if let Some((_, last)) = self.episode.last_mut() {
+            *last = reward;
+        }
+    }
+
+    fn observe_end(&mut self, _state: &State, _winner: Piece, params: &Params) {
+        self.learn_episode(params);
+    }
+
+    fn greedy_action(&self, state: &State) -> Option<Action> {
+        let mut best: Option<(Action, Value)> = None;
+        for (action, q) in self.q_values(state) {
+            if best.is_none_or(|(_, best_q)| q > best_q) {
+                best = Some((action, q));
+            }
+        }
+        best.map(|(action, _)| action)
+    }
+
+    /// Lists the Q value of every legal action in this state.
+    fn q_values(&self, state: &State) -> Vec<(Action, Value)> {
+        state.legal_actions(self.player).into_iter()
+            .map(|action| (action, self.q_value(state, &action)))
+            .collect()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
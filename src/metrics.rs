@@ -0,0 +1,122 @@
+/*
+Measures of policy quality that go beyond win/draw counts.
+Both compare an agent's greedy policy to perfect play from the solver,
+so they are only practical on boards small enough to solve.
+*/
+
+use std::collections::{HashMap, HashSet};
+
+use crate::agent::Agent;
+use crate::piece::Piece;
+use crate::q_agent::Value;
+use crate::rules::Rules;
+use crate::solver::Solver;
+use crate::state::*;
+
+pub struct Quality {
+    /// Reachable positions with the agent on the play.
+    pub positions: usize,
+    /// Fraction of those positions where the greedy move is an optimal move.
+    pub agreement: Value,
+    /// Result of the greedy policy against a best-response opponent: 1 win, 0 draw, -1 loss.
+    pub worst_case: i32,
+    /// Result the agent's seat gets with perfect play.
+    pub optimal: i32,
+}
+
+impl Quality {
+    /// How much a best-response opponent takes away compared to perfect play.
+    pub fn exploitability(&self) -> i32 {
+        self.optimal - self.worst_case
+    }
+}
+
+/// The most cells a board can have for `measure` to finish in reasonable time.
+/// The walk visits every reachable position, roughly 3^cells of them.
+const MAX_CELLS: i32 = 12;
+
+/// Can the quality of a policy on these rules be measured, i.e. is the board small enough to solve?
+pub fn feasible(rules: Rules) -> bool {
+    rules.board_size.x * rules.board_size.y <= MAX_CELLS
+}
+
+/// Measures the greedy policy of an agent in its own seat.
+/// Returns None if the agent has no greedy policy.
+pub fn measure(agent: &dyn Agent, rules: Rules, solver: &mut Solver) -> Option<Quality> {
+    let player = agent.player();
+    let start = State::new(rules);
+
+    // Every position either side can reach with the agent to move.
+    let mut seen = HashSet::new();
+    let mut stack = vec![start.clone()];
+    let mut positions = Vec::new();
+    while let Some(state) = stack.pop() {
+        if !seen.insert(state.clone()) || state.check_winner() != Piece::Empty {
+            continue;
+        }
+        for action in state.legal_actions(state.on_play()) {
+            let mut child = state.clone();
+            child.play_mut(&action);
+            stack.push(child);
+        }
+        if state.on_play() == player {
+            positions.push(state);
+        }
+    }
+
+    let mut agree = 0;
+    for state in positions.iter() {
+        let action = agent.greedy_action(state)?;
+        if solver.solve(state).moves.contains(&action) {
+            agree += 1;
+        }
+    }
+
+    let mut memo = HashMap::new();
+    let worst_case = best_response(agent, &start, &mut memo)?;
+    let optimal = if start.on_play() == player {
+        solver.value(&start)
+    }
+    else {
+        -solver.value(&start)
+    };
+
+    Some(Quality {
+        positions: positions.len(),
+        agreement: agree as Value / positions.len().max(1) as Value,
+        worst_case,
+        optimal,
+    })
+}
+
+/// Plays the agent's greedy move at its turns and every reply at the opponent's,
+/// returning the best the opponent can do from the agent's point of view.
+fn best_response(agent: &dyn Agent, state: &State, memo: &mut HashMap<State, i32>) -> Option<i32> {
+    let player = agent.player();
+    match state.check_winner() {
+        Piece::Empty => (),
+        Piece::Draw => return Some(0),
+        winner if winner == player => return Some(1),
+        _ => return Some(-1),
+    }
+    if let Some(value) = memo.get(state) {
+        return Some(*value);
+    }
+
+    let value = if state.on_play() == player {
+        let mut child = state.clone();
+        child.play_mut(&agent.greedy_action(state)?);
+        best_response(agent, &child, memo)?
+    }
+    else {
+        let mut worst = 1;
+        for action in state.legal_actions(state.on_play()) {
+            let mut child = state.clone();
+            child.play_mut(&action);
+            worst = worst.min(best_response(agent, &child, memo)?);
+        }
+        worst
+    };
+    memo.insert(state.clone(), value);
+    Some(value)
+}
@@ -0,0 +1,102 @@
+/*
+Saving and loading Q-tables.
+Tables are written either as a versioned binary file (a magic header, the format version, then bincode)
+or as readable JSON. Loading looks at the first bytes of the file to tell them apart.
+*/
+
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+
+use crate::q_agent::{Value, Frequency};
+use crate::piece::Piece;
+use crate::rules::Rules;
+use crate::state::Action;
+
+const MAGIC: &[u8; 4] = b"QTBL";
+pub const FORMAT_VERSION: u32 = 2;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Binary,
+    Json,
+}
+
+impl Format {
+    /// Picks JSON for `.json` files and binary for everything else.
+    fn from_path(path: &Path) -> Format {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => Format::Json,
+            _ => Format::Binary,
+        }
+    }
+}
+
+/// Everything needed to rebuild a trained agent.
+#[derive(Serialize, Deserialize)]
+pub struct SavedTable {
+    pub version: u32,
+    pub rules: Rules,
+    pub player: Piece,
+    pub symmetry: bool,
+    pub double_q: bool,
+    pub entries: Vec<SavedEntry>,
+    /// The second table of double Q-learning, empty otherwise.
+    pub second: Vec<SavedEntry>,
+}
+
+/// One Q-table entry, with the board written out row by row.
+#[derive(Serialize, Deserialize)]
+pub struct SavedEntry {
+    pub board: Vec<String>,
+    pub on_play: Piece,
+    pub action: Action,
+    pub value: Value,
+    pub frequency: Frequency,
+}
+
+/// Writes a table to disk, creating the parent directory if needed.
+pub fn write(path: &Path, table: &SavedTable) -> Result<(), Box<dyn Error>> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let bytes = match Format::from_path(path) {
+        Format::Json => serde_json::to_vec_pretty(table)?,
+        Format::Binary => {
+            let mut bytes = MAGIC.to_vec();
+            bytes.extend_from_slice(&table.version.to_le_bytes());
+            bytes.extend(bincode::serialize(table)?);
+            bytes
+        },
+    };
+    fs::write(path, bytes)?;
+    Ok(())
+}
+
+/// Reads a table written by `write`, in either format.
+pub fn read(path: &Path) -> Result<SavedTable, Box<dyn Error>> {
+    let bytes = fs::read(path)?;
+    let table: SavedTable = if bytes.starts_with(MAGIC) {
+        let header = MAGIC.len() + 4;
+        if bytes.len() < header {
+            return Err("truncated Q-table file".into());
+        }
+        let mut version = [0; 4];
+        version.copy_from_slice(&bytes[MAGIC.len()..header]);
+        check_version(u32::from_le_bytes(version))?;
+        bincode::deserialize(&bytes[header..])?
+    }
+    else {
+        serde_json::from_slice(&bytes)?
+    };
+    check_version(table.version)?;
+    Ok(table)
+}
+
+fn check_version(version: u32) -> Result<(), Box<dyn Error>> {
+    if version != FORMAT_VERSION {
+        return Err(format!("unsupported Q-table format version {} (expected {})", version, FORMAT_VERSION).into());
+    }
+    Ok(())
+}
@@ -4,7 +4,10 @@ It tracks which player can play a move, which spaces are already taken,
 and who won the game.
 */
 
-#[derive(PartialEq, Eq, Clone, Copy, Hash)]
+use std::fmt;
+use serde::{Serialize, Deserialize};
+
+#[derive(PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize)]
 pub enum Piece {
     Draw = -1, 
     Empty = 0, 
@@ -18,14 +21,24 @@ impl Piece {
             _ => 0,
         }
     }
+
+    /// The other player.
+    pub fn opponent(self) -> Piece {
+        match self {
+            Piece::P1 => Piece::P2,
+            Piece::P2 => Piece::P1,
+            other => other,
+        }
+    }
 }
-impl ToString for Piece {
-    fn to_string(&self) -> String {
-        match *self {
+impl fmt::Display for Piece {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
             Piece::Draw => "Draw",
             Piece::Empty => "Empty",
             Piece::P1 => "Player 1",
             Piece::P2 => "Player 2",
-        }.to_string()
+        };
+        write!(f, "{}", name)
     }
 }
\ No newline at end of file
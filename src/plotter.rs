@@ -1,17 +1,47 @@
 use plotters::prelude::*;
+use crate::metrics::Quality;
+use crate::agent::Params;
+use crate::piece::Piece;
 
 pub struct QResult {
     pub games_played: usize,
     pub p1_win: usize,
     pub p2_win: usize,
     pub draw: usize,
+    /// How the agent in each seat did against a random opponent, filled in by self-play runs.
+    pub seats: [SeatResult; 2],
 }
+
+/// Games one seat played against a random opponent.
+#[derive(Clone, Copy, Default)]
+pub struct SeatResult {
+    pub games_played: usize,
+    pub win: usize,
+    pub loss: usize,
+    pub draw: usize,
+}
+impl SeatResult {
+    /// Tallies the results of `result` for the given seat.
+    pub fn from_games(result: &QResult, seat: Piece) -> SeatResult {
+        let (win, loss) = match seat {
+            Piece::P1 => (result.p1_win, result.p2_win),
+            _ => (result.p2_win, result.p1_win),
+        };
+        SeatResult { games_played: result.games_played, win, loss, draw: result.draw }
+    }
+
+    /// Wins plus half the draws, as a share of the games played.
+    pub fn score(&self) -> f32 {
+        (self.win as f32 + self.draw as f32 / 2.) / self.games_played as f32
+    }
+}
+
 impl QResult {
     pub fn new(games_played: usize) -> QResult {
-        QResult { games_played, p1_win: 0, p2_win: 0, draw: 0 }
+        QResult { games_played, p1_win: 0, p2_win: 0, draw: 0, seats: [SeatResult::default(); 2] }
     }
 
-    pub fn _analyze(&self) {
+    pub fn analyze(&self) {
         // Analyze the results.
         let denom = self.games_played as f32;
         let n = 1000.;
@@ -26,6 +56,11 @@ impl QResult {
             self.p2_win, p2_ratio,
             self.draw, draw_ratio
         );
+        for (seat, result) in [Piece::P1, Piece::P2].iter().zip(self.seats.iter()) {
+            if result.games_played > 0 {
+                println!("{} vs random, W / L / D :: {} / {} / {}", seat, result.win, result.loss, result.draw);
+            }
+        }
     }
 }
 
@@ -94,4 +129,151 @@ pub fn visualize(result: Vec<QResult>, file_name: String, title: String)
  
      root.present()?;
      Ok(())
-}
\ No newline at end of file
+}
+// Plots the policy quality measured after each epoch.
+pub fn visualize_quality(result: Vec<Quality>, file_name: String, title: String)
+    -> Result<(), Box<dyn std::error::Error>>
+{
+    // Basic info
+    let path = format!("./plots/{}.png", file_name);
+    let size = (1290, 720);
+    let root = BitMapBackend::new(&path, size).into_drawing_area();
+    let dimension = (0.0..(result.len() as f32), -1.0f32..1.0f32);
+    root.fill(&WHITE)?;
+
+    // Builds the 2D graph.
+    let mut chart = ChartBuilder::on(&root)
+        .caption(title, ("sans-serif", 50).into_font())
+        .margin(5)
+        .x_label_area_size(30)
+        .y_label_area_size(30)
+        .build_cartesian_2d(dimension.0, dimension.1)?;
+
+    chart.configure_mesh().draw()?;
+
+    let point_size = 2;
+
+    // OPTIMAL MOVE AGREEMENT
+    let agreement_iter = result.iter().enumerate().map(|(i, q)| (i as f32, q.agreement as f32));
+    let agreement_series = LineSeries::new(agreement_iter, BLUE.filled()).point_size(point_size);
+    chart.draw_series(agreement_series)?
+        .label("Optimal move agreement")
+        .legend(|(x, y)|
+            PathElement::new(vec![(x, y), (x + 20, y)], BLUE)
+    );
+
+    // WORST CASE AGAINST A BEST RESPONSE
+    let worst_iter = result.iter().enumerate().map(|(i, q)| (i as f32, q.worst_case as f32));
+    let worst_series = LineSeries::new(worst_iter, RED.filled()).point_size(point_size);
+    chart.draw_series(worst_series)?
+        .label("Worst case vs best response")
+        .legend(|(x, y)|
+            PathElement::new(vec![(x, y), (x + 20, y)], RED)
+    );
+
+    // Create the line key.
+    chart
+    .configure_series_labels()
+    .background_style(WHITE.mix(0.8))
+    .border_style(BLACK)
+    .draw()?;
+
+    root.present()?;
+    Ok(())
+}
+
+// Plots how each seat scored against a random opponent after each epoch.
+pub fn visualize_seats(result: &[QResult], file_name: String, title: String)
+    -> Result<(), Box<dyn std::error::Error>>
+{
+    // Basic info
+    let path = format!("./plots/{}.png", file_name);
+    let size = (1290, 720);
+    let root = BitMapBackend::new(&path, size).into_drawing_area();
+    let dimension = (0.0..(result.len() as f32), 0.0f32..1.0f32);
+    root.fill(&WHITE)?;
+
+    // Builds the 2D graph.
+    let mut chart = ChartBuilder::on(&root)
+        .caption(title, ("sans-serif", 50).into_font())
+        .margin(5)
+        .x_label_area_size(30)
+        .y_label_area_size(30)
+        .build_cartesian_2d(dimension.0, dimension.1)?;
+
+    chart.configure_mesh().draw()?;
+
+    let point_size = 2;
+
+    for (index, (label, color)) in [("P1 score vs random", BLUE), ("P2 score vs random", RED)].into_iter().enumerate() {
+        let score_iter = result.iter().enumerate().map(|(i, r)| (i as f32, r.seats[index].score()));
+        let score_series = LineSeries::new(score_iter, color.filled()).point_size(point_size);
+        chart.draw_series(score_series)?
+            .label(label)
+            .legend(move |(x, y)|
+                PathElement::new(vec![(x, y), (x + 20, y)], color)
+        );
+    }
+
+    // Create the line key.
+    chart
+    .configure_series_labels()
+    .background_style(WHITE.mix(0.8))
+    .border_style(BLACK)
+    .draw()?;
+
+    root.present()?;
+    Ok(())
+}
+
+// Plots epsilon, eta and the softmax temperature at the start of each epoch.
+pub fn visualize_params(result: &[Params], file_name: String, title: String)
+    -> Result<(), Box<dyn std::error::Error>>
+{
+    // Basic info
+    let path = format!("./plots/{}.png", file_name);
+    let size = (1290, 720);
+    let root = BitMapBackend::new(&path, size).into_drawing_area();
+    let top = result.iter()
+        .map(|p| p.epsilon.max(p.eta).max(p.temperature) as f32)
+        .fold(0.0f32, f32::max);
+    let dimension = (0.0..(result.len() as f32), 0.0f32..top.max(f32::EPSILON));
+    root.fill(&WHITE)?;
+
+    // Builds the 2D graph.
+    let mut chart = ChartBuilder::on(&root)
+        .caption(title, ("sans-serif", 50).into_font())
+        .margin(5)
+        .x_label_area_size(30)
+        .y_label_area_size(30)
+        .build_cartesian_2d(dimension.0, dimension.1)?;
+
+    chart.configure_mesh().draw()?;
+
+    let point_size = 2;
+    let series = [
+        ("Epsilon", BLUE, result.iter().map(|p| p.epsilon).collect::<Vec<_>>()),
+        ("Eta", RED, result.iter().map(|p| p.eta).collect()),
+        ("Temperature", GREEN, result.iter().map(|p| p.temperature).collect()),
+    ];
+
+    for (label, color, values) in series {
+        let value_iter = values.into_iter().enumerate().map(|(i, v)| (i as f32, v as f32));
+        let value_series = LineSeries::new(value_iter, color.filled()).point_size(point_size);
+        chart.draw_series(value_series)?
+            .label(label)
+            .legend(move |(x, y)|
+                PathElement::new(vec![(x, y), (x + 20, y)], color)
+        );
+    }
+
+    // Create the line key.
+    chart
+    .configure_series_labels()
+    .background_style(WHITE.mix(0.8))
+    .border_style(BLACK)
+    .draw()?;
+
+    root.present()?;
+    Ok(())
+}
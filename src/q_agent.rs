@@ -1,90 +1,517 @@
 // 
 
+use std::any::Any;
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::rc::Rc;
+use std::error::Error;
+use std::path::Path;
 use bracket_lib::random::RandomNumberGenerator;
 
 use crate::state::*;
 use crate::vector::*;
 use crate::piece::*;
+use crate::rules::Rules;
+use crate::symmetry::{Symmetry, Symmetries};
+use crate::persist::{self, SavedTable, SavedEntry};
+use crate::agent::{Agent, Params};
+use crate::exploration::{self, Exploration};
+use crate::replay::ReplayBuffer;
 
 pub type Value = f64;
-type Frequency = u32;
+pub type Frequency = u32;
 type Legal = bool;
-type QTable = HashMap<(State, Action), (Value, Frequency)>;
+pub type QTable = HashMap<(State, Action), (Value, Frequency), KeyHash>;
 type Actions = Vec<(Action, Legal)>;
 
+/// How the target of each update bootstraps from the next state,
+/// the board at our next turn for every rule but negamax.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum UpdateRule {
+    /// Off-policy, the best Q value of the next state.
+    QLearning,
+    /// On-policy, the Q value of the action actually taken next.
+    Sarsa,
+    /// On-policy, the expectation over the legal actions of the next state under the agent's exploration.
+    ExpectedSarsa,
+    /// Zero-sum, the negated best Q value of the opponent's reply.
+    /// The table also learns the opponent's moves, from the opponent's point of view.
+    /// Needs zero-sum rewards, a draw worth 0 and a loss worth minus a win.
+    Negamax,
+}
+
+/// How an eligibility trace grows when its pair is visited again.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Trace {
+    /// Reset to 1.
+    Replacing,
+    /// Add 1 to what is left.
+    Accumulating,
+}
+
+/// How far each update moves a Q value toward its target.
+#[derive(Clone, Copy, PartialEq)]
+pub enum StepSize {
+    /// The game's eta for every entry.
+    Fixed,
+    /// 1/N(s, a), the running average of every target the entry has seen.
+    InverseCount { floor: Value },
+    /// 1/N(s, a)^omega, with omega between 0.5 and 1 forgetting old targets more slowly than 1/N.
+    Polynomial { omega: Value, floor: Value },
+}
+
+impl StepSize {
+    /// The step for an entry updated `frequency` times, counting this update.
+    fn step(&self, eta: Value, frequency: Frequency) -> Value {
+        let n = frequency.max(1) as Value;
+        match *self {
+            StepSize::Fixed => eta,
+            StepSize::InverseCount { floor } => (1. / n).max(floor),
+            StepSize::Polynomial { omega, floor } => (1. / n.powf(omega)).max(floor),
+        }
+    }
+}
+
 pub struct QLearningAgent {
-    q_table:    QTable,
+    // Shared between the seats of a self-play game when they learn together.
+    q_table:    Rc<RefCell<QTable>>,
+    // Double Q-learning keeps a second table, each update teaches one of them.
+    double_table: Option<QTable>,
     actions:    Actions,
+    opponent_actions: Actions,
+    rules:      Rules,
+    player:     Piece,
+    symmetries: Symmetries,
+    update_rule: UpdateRule,
+    exploration: Exploration,
+    step_size:  StepSize,
+    // The value of pairs that have never been updated.
+    initial_q:  Value,
+    // SARSA waits for the next action before updating the last one.
+    pending:    Option<(State, Action, Value)>,
+    // Eligibility of every pair visited this game, when traces are on.
+    trace:      Option<Trace>,
+    traces:     HashMap<(State, Action), Value, KeyHash>,
+    // Negamax learns the opponent's reply to the board our last move left.
+    last_afterstate: Option<State>,
 }
 
 impl QLearningAgent {
-    pub fn new(board_size: Vector2, player: Piece) -> QLearningAgent {
-        let q_table = HashMap::new();
-        let actions = Self::create_actions(board_size, player);
-        QLearningAgent { q_table, actions }
+    pub fn new(rules: Rules, player: Piece) -> QLearningAgent {
+        let q_table = Rc::new(RefCell::new(QTable::default()));
+        let actions = Self::create_actions(rules, player);
+        let opponent_actions = Self::create_actions(rules, player.opponent());
+        let symmetries = Symmetries::new(rules, false);
+        let update_rule = UpdateRule::QLearning;
+        QLearningAgent { q_table, double_table: None, actions, opponent_actions, rules, player, symmetries,
+            update_rule, exploration: Exploration::EpsilonGreedy,
+            step_size: StepSize::Fixed, initial_q: 0., pending: None, trace: None, traces: HashMap::default(), last_afterstate: None }
     }
 
-    /// Uses the Q-Table to choose the best action, sometimes choosing a random action.
-    pub fn choose_action(&mut self, state: State, rng: &mut RandomNumberGenerator, epsilon: Value) -> Action {
-        // Mark illegal actions and check if there are any moves left.
-        self.update_actions(&state);
-        if !Self::legal_actions(&self.actions) {
-            panic!("No legal actions");
+    /// Keeps eligibility traces so one reward reaches every earlier move of the game.
+    /// With Q-learning this is Watkins' Q(lambda), cutting the traces after exploratory moves,
+    /// with SARSA it is SARSA(lambda). Lambda comes from the game's parameters.
+    pub fn with_traces(mut self, trace: Option<Trace>) -> QLearningAgent {
+        self.trace = trace;
+        self.check_double_q();
+        self
+    }
+
+    /// Learns two Q-tables to remove the maximization bias of Q-learning.
+    /// Actions are chosen on the mean of both tables.
+    /// Only one-step Q-learning knows how to split its updates between the tables.
+    pub fn with_double_q(mut self, enabled: bool) -> QLearningAgent {
+        self.double_table = if enabled { Some(QTable::default()) } else { None };
+        self.check_double_q();
+        self
+    }
+
+    fn check_double_q(&self) {
+        assert!(self.double_table.is_none() || (self.update_rule == UpdateRule::QLearning && self.trace.is_none()),
+            "double Q-learning needs the Q-learning update rule without traces");
+    }
+
+    /// Chooses the learning rate of each update, fixed or shrinking with the entry's visits.
+    pub fn with_step_size(mut self, step_size: StepSize) -> QLearningAgent {
+        self.step_size = step_size;
+        self
+    }
+
+    /// Sets the value of pairs that have never been updated.
+    /// An optimistic value such as 1.0 makes greedy play try every move before settling.
+    pub fn with_initial_q(mut self, initial_q: Value) -> QLearningAgent {
+        self.initial_q = initial_q;
+        self
+    }
+
+    /// Chooses how moves are picked while learning.
+    pub fn with_exploration(mut self, exploration: Exploration) -> QLearningAgent {
+        self.exploration = exploration;
+        self
+    }
+
+    /// Chooses how updates bootstrap from the next state.
+    pub fn with_update_rule(mut self, update_rule: UpdateRule) -> QLearningAgent {
+        self.update_rule = update_rule;
+        self.check_double_q();
+        self
+    }
+
+    /// Stores every state under its canonical rotation/reflection,
+    /// so equivalent positions share one set of Q-table entries.
+    pub fn with_symmetry(mut self, enabled: bool) -> QLearningAgent {
+        self.symmetries = Symmetries::new(self.rules, enabled);
+        self
+    }
+
+    /// Learns in the same Q-table as another agent, e.g. the other seat in self-play.
+    /// Each seat then bootstraps from the values the other seat learned for its replies,
+    /// negated as only negamax does, so both agents must use that update rule.
+    pub fn with_shared_table(mut self, other: &QLearningAgent) -> QLearningAgent {
+        assert!(self.update_rule == UpdateRule::Negamax && other.update_rule == UpdateRule::Negamax,
+            "a shared table needs the negamax update rule");
+        self.q_table = Rc::clone(&other.q_table);
+        self
+    }
+
+    /// Has this agent learned anything yet?
+    pub fn is_empty(&self) -> bool {
+        self.q_table.borrow().is_empty() && self.double_table.as_ref().is_none_or(|t| t.is_empty())
+    }
+
+    /// The rules this agent was trained on.
+    pub fn rules(&self) -> Rules {
+        self.rules
+    }
+
+    /// How this agent keys its table on canonical boards.
+    pub fn symmetries(&self) -> &Symmetries {
+        &self.symmetries
+    }
+
+    /// Saves the Q-table with its visit counts, rules and player.
+    /// Under double Q-learning both tables are saved, and the agent loads with double Q-learning on.
+    /// Paths ending in `.json` are written as JSON, anything else as binary.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let table = SavedTable {
+            version: persist::FORMAT_VERSION,
+            rules: self.rules,
+            player: self.player,
+            symmetry: self.symmetries.is_enabled(),
+            double_q: self.double_table.is_some(),
+            entries: self.saved_entries(&self.q_table.borrow()),
+            second: self.double_table.as_ref().map_or_else(Vec::new, |table| self.saved_entries(table)),
+        };
+        persist::write(path.as_ref(), &table)
+    }
+
+    /// The entries of one table as they are saved.
+    fn saved_entries(&self, table: &QTable) -> Vec<SavedEntry> {
+        table.iter()
+            .map(|((state, action), (value, frequency))| {
+                SavedEntry {
+                    board: state.rows(),
+                    on_play: state.on_play(),
+                    action: *action,
+                    value: *value,
+                    frequency: *frequency,
+                }
+            })
+            .collect()
+    }
+
+    /// Loads an agent saved with `save`, in either format.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<QLearningAgent, Box<dyn Error>> {
+        let table = persist::read(path.as_ref())?;
+        let mut agent = QLearningAgent::new(table.rules, table.player)
+            .with_symmetry(table.symmetry)
+            .with_double_q(table.double_q);
+        let rules = table.rules;
+        let read_entries = |entries: Vec<SavedEntry>| -> Result<QTable, Box<dyn Error>> {
+            let mut q_table = QTable::default();
+            for entry in entries.into_iter() {
+                let state = State::from_rows(rules, &entry.board, entry.on_play)
+                    .ok_or("saved board does not match the saved rules")?;
+                q_table.insert((state, entry.action), (entry.value, entry.frequency));
+            }
+            Ok(q_table)
+        };
+        *agent.q_table.borrow_mut() = read_entries(table.entries)?;
+        if let Some(second) = agent.double_table.as_mut() {
+            *second = read_entries(table.second)?;
         }
-        let p = rng.range(0., 1.);
+        Ok(agent)
+    }
+
+    /// Updates the Q value using the Bellman Equation.
+    /// The update waits for the next `choose_action`, where the update rule values the next state,
+    /// or is replaced by the final observation of the action if the game ends first.
+    pub fn update_q(&mut self, prev_state: &State, state: &State, action: &Action, 
+        reward: Value, rng: &mut RandomNumberGenerator, params: &Params) {
+        if self.update_rule == UpdateRule::Negamax {
+            assert!(params.rewards.is_zero_sum(), "negamax needs zero-sum rewards, e.g. RewardScheme::new(1., -1., 0.)");
+            if state.check_winner() != Piece::Empty && prev_state.on_play() == state.on_play() {
+                self.negamax_end(prev_state, action, state, params);
+                return;
+            }
+        }
+
+        // Nothing follows a finished game, so the reward is the whole target.
+        if state.check_winner() != Piece::Empty {
+            // The end of the game replaces an update still waiting on this action.
+            if self.pending.as_ref().is_some_and(|(s, a, _)| s == prev_state && a == action) {
+                self.pending = None;
+            }
+            self.flush_pending(rng, params);
+            self.learn(prev_state, action, reward, rng, params);
+            return;
+        }
+
+        // Nothing was chosen since the last transition, so it ended without a next action.
+        self.flush_pending(rng, params);
+        if self.update_rule == UpdateRule::Negamax {
+            // Negamax values the board our move left, once the opponent's reply on it is learned.
+            self.last_afterstate = Some(state.clone());
+        }
+        self.pending = Some((prev_state.clone(), *action, reward));
+    }
+
+    /// The best Q value of this agent's actions on a board, 0 once the game is over.
+    pub fn state_value(&self, state: &State) -> Value {
+        if state.check_winner() != Piece::Empty {
+            return 0.;
+        }
+        self.max_q_action(state).1
+    }
+
+    /// Moves the Q value of a pair toward a target from outside of play, e.g. when planning or replaying.
+    /// Unlike `update_q` it leaves eligibility traces, waiting updates and visit counts alone,
+    /// so the counts stay those of moves actually played.
+    /// Under double Q-learning it teaches one of the tables, picked at random.
+    pub fn update_toward(&mut self, state: &State, action: &Action, target: Value,
+        rng: &mut RandomNumberGenerator, params: &Params) {
+        let k = self.key(state, action);
+        let second = self.double_table.is_some() && rng.range(0, 2) == 1;
+        self.update_picked(k, target, second, false, params);
+    }
 
-        // On-policy/greedy action
-        if p > epsilon {
-            let index = Self::max_q_action(&self.q_table, &self.actions, state).0;
-            self.actions[index].0
+    /// Updates the second table if picked and there is one, else the main table.
+    fn update_picked(&mut self, k: (State, Action), target: Value, second: bool, visit: bool, params: &Params) {
+        match self.double_table.as_mut() {
+            Some(table) if second => Self::update_table(table, k, target, params.eta, self.step_size, self.initial_q, visit),
+            _ => Self::update_table(&mut self.q_table.borrow_mut(), k, target, params.eta, self.step_size, self.initial_q, visit),
+        }
+    }
+
+    /// Minus the best Q value of the player on the play, 0 once the game is over.
+    fn negamax_q(&self, state: &State) -> Value {
+        match self.max_q_action(state) {
+            (Some(_), best) if state.check_winner() == Piece::Empty => -best,
+            _ => 0.,
+        }
+    }
+
+    /// The opponent's reply ended the game, so under negamax only that final move learns the result.
+    /// Our last move keeps its one target, the negated value of the reply, now that the reply is learned,
+    /// in place of the update still waiting on it.
+    fn negamax_end(&mut self, prev_state: &State, action: &Action, state: &State, params: &Params) {
+        self.pending = None;
+        let mut afterstate = prev_state.clone();
+        afterstate.play_mut(action);
+        self.last_afterstate = Some(afterstate.clone());
+        self.learn_opponent(state, params);
+        let reward = params.rewards.transition(prev_state, &afterstate, action.player, params.gamma);
+        let target = reward + params.gamma * self.negamax_q(&afterstate);
+        self.update_entry(prev_state, action, target, params);
+    }
+
+    /// Learns the opponent's move between the board our last move left and the one we see now.
+    /// Its reward is what the game would give the opponent for it.
+    /// With a shared table the opponent's seat learns it instead.
+    fn learn_opponent(&mut self, state: &State, params: &Params) {
+        let Some(afterstate) = self.last_afterstate.take() else { return };
+        if Rc::strong_count(&self.q_table) > 1 {
+            return;
+        }
+        let Some(action) = Self::action_between(&afterstate, state) else { return };
+        let reward = params.rewards.transition(&afterstate, state, action.player, params.gamma);
+        let target = reward + params.gamma * self.negamax_q(state);
+        self.update_entry(&afterstate, &action, target, params);
+    }
+
+    /// The single move that turns one board into another, if there is one.
+    fn action_between(before: &State, after: &State) -> Option<Action> {
+        before.legal_actions(before.on_play()).into_iter()
+            .find(|action| {
+                let mut next = before.clone();
+                next.play_mut(action);
+                next == *after
+            })
+    }
+
+    /// Double Q-learning: a table picked at random chooses the best action on the board at our next turn,
+    /// ties broken at random as in play, the other table values it, and only the picked table is updated.
+    fn double_update(&mut self, prev_state: &State, action: &Action, reward: Value, state: &State,
+        rng: &mut RandomNumberGenerator, params: &Params) {
+        let second = rng.range(0, 2) == 1;
+        let q_table = self.q_table.borrow();
+        let next_q = match &self.double_table {
+            Some(other) => {
+                let (learner, judge) = if second { (other, &*q_table) } else { (&*q_table, other) };
+                match self.max_q_by(state, |k| learner.get(k).map(|(q, _frq)| *q), Some(rng)).0 {
+                    Some(best) => judge.get(&self.key(state, &best)).map_or(self.initial_q, |(q, _frq)| *q),
+                    None => 0.,
+                }
+            },
+            None => 0.,
+        };
+        drop(q_table);
+        let k = self.key(prev_state, action);
+        let target = reward + params.gamma * next_q;
+        self.update_picked(k, target, second, true, params);
+    }
+
+    /// Moves a pair toward a target with nothing left to bootstrap from,
+    /// teaching one table picked at random under double Q-learning.
+    fn learn(&mut self, prev_state: &State, action: &Action, target: Value,
+        rng: &mut RandomNumberGenerator, params: &Params) {
+        if self.double_table.is_some() {
+            let second = rng.range(0, 2) == 1;
+            self.update_picked(self.key(prev_state, action), target, second, true, params);
         }
-        // Random action
         else {
-            self.choose_random_action(rng)
+            self.update_entry(prev_state, action, target, params);
+        }
+    }
+
+    /// Moves the Q value of a state/action pair toward a target.
+    /// Double Q-learning teaches one table at a time through `double_update` and `learn` instead.
+    fn update_entry(&mut self, prev_state: &State, action: &Action, target: Value, params: &Params) {
+        let k = self.key(prev_state, action);
+        if let Some(trace) = self.trace {
+            self.trace_update(k, target, trace, params);
+            return;
         }
+        Self::update_table(&mut self.q_table.borrow_mut(), k, target, params.eta, self.step_size, self.initial_q, true);
     }
 
-    /// Chooses an action at random from among the legal actions.
-    pub fn choose_random_action(&self, rng: &mut RandomNumberGenerator) -> Action {
-        let len = self.actions.len();
-        loop {
-            let index = rng.range(0, len);
-            let (action, legal) = self.actions[index];
-            if legal {
-                return action
+    /// Spreads the TD error of one pair over every pair with an eligibility trace,
+    /// then decays all the traces by gamma * lambda.
+    fn trace_update(&mut self, k: (State, Action), target: Value, trace: Trace, params: &Params) {
+        let delta = target - self.entry_value(&k).unwrap_or(self.initial_q);
+        let eligibility = self.traces.entry(k.clone()).or_insert(0.);
+        *eligibility = match trace {
+            Trace::Replacing => 1.,
+            Trace::Accumulating => *eligibility + 1.,
+        };
+
+        let decay = params.gamma * params.lambda;
+        let mut q_table = self.q_table.borrow_mut();
+        for table in [Some(&mut *q_table), self.double_table.as_mut()].into_iter().flatten() {
+            table.entry(k.clone()).or_insert((self.initial_q, 0)).1 += 1;
+        }
+        for (key, eligibility) in self.traces.iter_mut() {
+            for table in [Some(&mut *q_table), self.double_table.as_mut()].into_iter().flatten() {
+                let entry = table.entry(key.clone()).or_insert((self.initial_q, 0));
+                entry.0 += self.step_size.step(params.eta, entry.1) * delta * *eligibility;
             }
+            *eligibility *= decay;
         }
+        // Forget pairs whose traces have faded away.
+        self.traces.retain(|_, eligibility| *eligibility > 1e-4);
     }
 
-    /// Updates the Q value using the Bellman Equation.
-    pub fn update_q(&mut self, prev_state: State, state: State, action: &Action, 
-        reward: Value, eta: Value, gamma: Value) {
-        // max_q is the largest q value given the next state and set of available actions.
-        let max_q = Self::max_q_action(&self.q_table, &self.actions, state).1;
-        let k = (prev_state, *action);
-
-        // Search for the entry in the table.
-        match self.q_table.get_mut(&k) {
-            // Add new entry.
-            None => {
-                let mut v = (0., 1);
-                v.0 += eta * (reward + gamma * max_q);
-                self.q_table.insert(k, v);
-            },
-            // Update the existing entry.
-            Some((q_value, freq)) => {
-                *q_value += eta * (reward + gamma * max_q - *q_value);
-                *freq += 1;
+    /// Moves the Q value of an entry toward a target, counting a visit of it if asked.
+    /// Entries start from the initial value, and steps never go further than the first visit's.
+    fn update_table(table: &mut QTable, k: (State, Action), target: Value,
+        eta: Value, step_size: StepSize, initial_q: Value, visit: bool) {
+        let (q_value, freq) = table.entry(k).or_insert((initial_q, 0));
+        if visit {
+            *freq += 1;
+        }
+        *q_value += step_size.step(eta, (*freq).max(1)) * (target - *q_value);
+    }
+
+    /// Finishes a waiting update with no next board to bootstrap from.
+    fn flush_pending(&mut self, rng: &mut RandomNumberGenerator, params: &Params) {
+        if let Some((prev_state, action, reward)) = self.pending.take() {
+            self.learn(&prev_state, &action, reward, rng, params);
+        }
+    }
+
+    /// The Q-table key for a state and action, on the canonical board.
+    fn key(&self, state: &State, action: &Action) -> (State, Action) {
+        self.symmetries.key(state, action)
+    }
+
+    /// The learned value of a key, the mean of both tables under double Q-learning.
+    fn entry_value(&self, k: &(State, Action)) -> Option<Value> {
+        let first = self.q_table.borrow().get(k).map(|(q, _frq)| *q);
+        match &self.double_table {
+            None => first,
+            Some(table) => {
+                let second = table.get(k).map(|(q, _frq)| *q);
+                if first.is_none() && second.is_none() {
+                    None
+                }
+                else {
+                    Some((first.unwrap_or(self.initial_q) + second.unwrap_or(self.initial_q)) / 2.)
+                }
             },
-        };
+        }
+    }
+
+    /// The Q value and visit count of every legal action, canonicalizing the board only once.
+    fn legal_entries(&self, state: &State) -> Vec<(Action, Value, Frequency)> {
+        let (canonical, symmetry) = self.symmetries.canonical(state);
+        let mut k = (canonical, self.actions[0].0);
+        let q_table = self.q_table.borrow();
+        self.actions_on(state).iter()
+            .filter(|(action, _)| state.is_legal(action))
+            .map(|(action, _)| {
+                k.1 = self.transform_action(action, symmetry);
+                let first = q_table.get(&k);
+                let (q, frequency) = match &self.double_table {
+                    // A single lookup for the common case of one table.
+                    None => first.map_or((self.initial_q, 0), |(q, frq)| (*q, *frq)),
+                    Some(table) => {
+                        let frequency = first.map_or(0, |(_q, frq)| *frq) + table.get(&k).map_or(0, |(_q, frq)| *frq);
+                        (self.entry_value(&k).unwrap_or(self.initial_q), frequency)
+                    },
+                };
+                (*action, q, frequency)
+            })
+            .collect()
+    }
+
+    /// Looks up a single Q value, the initial value if it has never been updated.
+    pub fn q_value(&self, state: &State, action: &Action) -> Value {
+        self.entry_value(&self.key(state, action)).unwrap_or(self.initial_q)
+    }
+
+    /// The value of a state under the exploring policy, given the Q values and visit counts of its
+    /// legal actions: each Q value weighted by how likely it is to be chosen.
+    fn expected_q(&self, q_values: &[(Action, Value)], counts: &[Frequency], greedy: Action, params: &Params) -> Value {
+        let probabilities = self.exploration.probabilities(q_values, counts, greedy, params);
+        q_values.iter().zip(probabilities)
+            .map(|((_, q), p)| p * q)
+            .sum()
     }
 
 
 
     /// Creates the initial set of legal actions.
-    fn create_actions(board_size: Vector2, player: Piece) -> Vec<(Action, Legal)> {
+    /// Under gravity rules there is one action per column.
+    fn create_actions(rules: Rules, player: Piece) -> Vec<(Action, Legal)> {
+        let board_size = rules.board_size;
         let mut actions = Vec::new();
+        if rules.gravity {
+            for j in 0..board_size.y {
+                let position = Vector2::new(0, j);
+                actions.push((Action{player, position}, true));
+            }
+            return actions;
+        }
         for i in 0..board_size.x {
             for j in 0..board_size.y {
                 let position = Vector2::new(i, j);
@@ -97,37 +524,60 @@ impl QLearningAgent {
     /// Removes actions that are no longer legal.
     fn update_actions(&mut self, state: &State) {
         for (action, legal) in self.actions.iter_mut() {
-            *legal = state.get_piece(&action.position) == Piece::Empty;
+            *legal = state.is_legal(action);
         }
     }
 
-    /// Chooses the action with the highest Q value given some state.
-    /// Returns an index into the action table and the actual Q value;
-    fn max_q_action(q_table: &QTable, actions: &Actions, state: State) -> (usize, Value) {
-        let mut q_value = 0.;
-        let mut index = 0;
-        let mut k = (state, actions[0].0);
+    /// Moves an action onto the canonical board.
+    fn transform_action(&self, action: &Action, symmetry: Symmetry) -> Action {
+        self.symmetries.action(action, symmetry)
+    }
 
-        for (i, (action, legal)) in actions.iter().enumerate() {
-            if *legal {
-                // Make sure we choose a legal action.
-                if q_value == 0. {
-                    index = i;
-                }
-                // Swap actions in the key, keeping state constant.
-                k.1 = *action;
-                match q_table.get(&k) {
-                    None => (),
-                    Some((q, _frq)) => {
-                        if *q > q_value {
-                            q_value = *q;
-                            index = i;
-                        }
-                    }
+    /// Chooses the action with the highest Q value given some state, the first one on ties,
+    /// and that Q value. The action is one of the player on the play, None if it has no legal action.
+    /// The action always refers to the real board, the lookup uses the canonical one.
+    /// Play breaks ties at random; taking the first keeps `greedy_action` a fixed policy to measure.
+    fn max_q_action(&self, state: &State) -> (Option<Action>, Value) {
+        self.max_q_by(state, |k| self.entry_value(k), None)
+    }
+
+    /// Same as `max_q_action`, valuing each key with the given lookup, ties broken at random with a generator.
+    /// Pairs never updated count as the initial Q value, a board without legal actions is worth 0.
+    fn max_q_by<F>(&self, state: &State, lookup: F, mut rng: Option<&mut RandomNumberGenerator>) -> (Option<Action>, Value)
+        where F: Fn(&(State, Action)) -> Option<Value> {
+        let mut best = (None, Value::NEG_INFINITY);
+        let mut ties = 1;
+        let (canonical, symmetry) = self.symmetries.canonical(state);
+        let mut k = (canonical, self.actions[0].0);
+
+        for (action, _) in self.actions_on(state).iter() {
+            if !state.is_legal(action) {
+                continue;
+            }
+            // Swap actions in the key, keeping state constant.
+            k.1 = self.transform_action(action, symmetry);
+            let q = lookup(&k).unwrap_or(self.initial_q);
+            if q > best.1 {
+                best = (Some(*action), q);
+                ties = 1;
+            }
+            else if let (true, Some(rng)) = (q == best.1, rng.as_deref_mut()) {
+                // Keeps the n-th tie with probability 1/n, as `exploration::greedy` does.
+                ties += 1;
+                if rng.range(0, ties) == 0 {
+                    best.0 = Some(*action);
                 }
             }
         }
-        (index, q_value)
+        if best.0.is_none() {
+            return (None, 0.);
+        }
+        best
+    }
+
+    /// The action table of the player on the play.
+    fn actions_on(&self, state: &State) -> &Actions {
+        if state.on_play() == self.player { &self.actions } else { &self.opponent_actions }
     }
 
     /// Tells if we have any legal actions left.
@@ -142,3 +592,152 @@ impl QLearningAgent {
     }
 
 }
+
+impl Agent for QLearningAgent {
+    fn player(&self) -> Piece {
+        self.player
+    }
+
+    /// Uses the Q-Table to choose the best action, sometimes choosing a random action.
+    fn choose_action(&mut self, state: &State, rng: &mut RandomNumberGenerator, params: &Params) -> Option<Action> {
+        // Mark illegal actions and check if there are any moves left.
+        self.update_actions(state);
+        if !Self::legal_actions(&self.actions) {
+            panic!("No legal actions");
+        }
+        // Negamax bootstraps our last move from the opponent's reply, so the reply is learned first.
+        let afterstate = self.last_afterstate.clone();
+        if self.update_rule == UpdateRule::Negamax {
+            self.learn_opponent(state, params);
+        }
+
+        // On-policy/greedy action, or an exploring one.
+        // Ties between the best actions are broken at random.
+        let entries = self.legal_entries(state);
+        let q_values: Vec<(Action, Value)> = entries.iter().map(|(action, q, _)| (*action, *q)).collect();
+        let counts: Vec<Frequency> = entries.iter().map(|(_, _, frq)| *frq).collect();
+        let greedy = exploration::greedy(&q_values, rng);
+        let action = self.exploration.choose(&q_values, &counts, greedy, rng, params);
+        let best_q = q_values.iter().map(|(_, q)| *q).fold(Value::NEG_INFINITY, Value::max);
+        let action_q = q_values.iter().find(|(a, _)| *a == action).map_or(self.initial_q, |(_, q)| *q);
+
+        // The previous action bootstraps from this board, the next one we are on the play.
+        if let Some((prev_state, prev_action, reward)) = self.pending.take() {
+            if self.double_table.is_some() {
+                self.double_update(&prev_state, &prev_action, reward, state, rng, params);
+            }
+            else {
+                let next_q = match self.update_rule {
+                    // SARSA from the action just chosen.
+                    UpdateRule::Sarsa => action_q,
+                    UpdateRule::ExpectedSarsa => self.expected_q(&q_values, &counts, greedy, params),
+                    UpdateRule::Negamax => afterstate.map_or(0., |afterstate| self.negamax_q(&afterstate)),
+                    // Q-learning from the best action.
+                    _ => best_q,
+                };
+                self.update_entry(&prev_state, &prev_action, reward + params.gamma * next_q, params);
+            }
+        }
+
+        // Watkins' Q(lambda) only follows the greedy policy back, so exploring cuts the traces.
+        if action_q < best_q && self.update_rule == UpdateRule::QLearning {
+            self.traces.clear();
+        }
+        Some(action)
+    }
+
+    fn observe(&mut self, prev_state: &State, action: &Action, reward: Value, state: &State,
+        rng: &mut RandomNumberGenerator, params: &Params) {
+        self.update_q(prev_state, state, action, reward, rng, params);
+    }
+
+    /// The final observations have already settled every waiting update.
+    fn observe_end(&mut self, state: &State, _winner: Piece, params: &Params) {
+        self.pending = None;
+        self.learn_opponent(state, params);
+        self.traces.clear();
+    }
+
+    /// One-step Q-learning on each sampled transition, bootstrapping from the board at our next turn.
+    /// The importance-sampling weight scales how far each update moves.
+    fn replay(&mut self, buffer: &mut ReplayBuffer, rng: &mut RandomNumberGenerator, params: &Params) {
+        for (index, weight) in buffer.sample(rng) {
+            let transition = buffer.get(index).clone();
+            let next_q = if transition.terminal { 0. } else { self.state_value(&transition.next) };
+            let target = transition.reward + params.gamma * next_q;
+            let q = self.q_value(&transition.state, &transition.action);
+            self.update_toward(&transition.state, &transition.action, q + weight * (target - q), rng, params);
+            buffer.update_priority(index, target - q);
+        }
+    }
+
+    fn greedy_action(&self, state: &State) -> Option<Action> {
+        self.max_q_action(state).0
+    }
+
+    /// Lists the Q value of every legal action in this state.
+    fn q_values(&self, state: &State) -> Vec<(Action, Value)> {
+        self.legal_entries(state).into_iter().map(|(action, q, _)| (action, q)).collect()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::RandomAgent;
+    use crate::game::Game;
+
+    /// Trains an agent for a few games against random play.
+    fn trained(rules: Rules, double_q: bool) -> Game {
+        let agent = QLearningAgent::new(rules, Piece::P2).with_double_q(double_q);
+        let mut game = Game::new(rules, Box::new(RandomAgent::new(Piece::P1)), Box::new(agent), 0.1, 0.1, 0.9, 0.);
+        for _ in 0..200 {
+            game.play(false);
+            game.reset();
+        }
+        game
+    }
+
+    fn round_trip(rules: Rules, double_q: bool, extension: &str) {
+        let game = trained(rules, double_q);
+        let agent = game.agent(Piece::P2).as_any().downcast_ref::<QLearningAgent>().unwrap();
+        let path = std::env::temp_dir()
+            .join(format!("q_learning_{}_{}_{}.{}", std::process::id(), rules.win_length, double_q, extension));
+        agent.save(&path).unwrap();
+        let loaded = QLearningAgent::load(&path);
+        let _ = std::fs::remove_file(&path);
+        let loaded = loaded.unwrap();
+
+        assert!(loaded.rules() == rules && loaded.player == agent.player);
+        assert!(!agent.q_table.borrow().is_empty());
+        // Keys are rebuilt from their rows, so equal tables also mean the rebuilt hashes match.
+        assert!(*loaded.q_table.borrow() == *agent.q_table.borrow());
+        assert!(loaded.double_table == agent.double_table);
+    }
+
+    #[test]
+    fn saved_tables_load_back_unchanged() {
+        for extension in ["bin", "json"] {
+            round_trip(Rules::tic_tac_toe(), false, extension);
+            round_trip(Rules::tic_tac_toe(), true, extension);
+            round_trip(Rules::connect_four(), false, extension);
+        }
+    }
+
+    #[test]
+    fn loading_rejects_other_versions() {
+        let path = std::env::temp_dir().join(format!("q_learning_{}_version.json", std::process::id()));
+        trained(Rules::tic_tac_toe(), false).agent(Piece::P2).as_any()
+            .downcast_ref::<QLearningAgent>().unwrap().save(&path).unwrap();
+        let json = std::fs::read_to_string(&path).unwrap()
+            .replace(&format!("\"version\": {}", persist::FORMAT_VERSION), "\"version\": 0");
+        std::fs::write(&path, json).unwrap();
+        let loaded = QLearningAgent::load(&path);
+        let _ = std::fs::remove_file(&path);
+        assert!(loaded.is_err());
+    }
+}
@@ -0,0 +1,147 @@
+/*
+Experience replay.
+The game remembers the transitions of every game in a fixed-size buffer,
+and between games an agent learns from a mini-batch sampled out of it,
+so rare transitions such as early wins are learned from more than once.
+Prioritized sampling prefers transitions with a large TD error,
+and importance-sampling weights make up for the bias that brings.
+*/
+
+use bracket_lib::random::RandomNumberGenerator;
+
+use crate::q_agent::Value;
+use crate::state::*;
+
+/// One move of one player, and the board at that player's next turn or the end of the game.
+#[derive(Clone)]
+pub struct Transition {
+    pub state:    State,
+    pub action:   Action,
+    pub reward:   Value,
+    pub next:     State,
+    pub terminal: bool,
+}
+
+/// How transitions are drawn from the buffer.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Sampling {
+    /// Every stored transition is equally likely.
+    Uniform,
+    /// Drawn with probability proportional to |TD error|^alpha,
+    /// each weighted by (N * P(i))^-beta relative to the largest weight in the batch.
+    Prioritized { alpha: Value, beta: Value },
+}
+
+pub struct ReplayBuffer {
+    capacity:   usize,
+    batch:      usize,
+    sampling:   Sampling,
+    transitions: Vec<Transition>,
+    // Where the next transition goes once the buffer is full, overwriting the oldest.
+    next:       usize,
+    // Sum tree over the priorities: leaf i sits at capacity + i, every node holds the sum of its children.
+    tree:       Vec<Value>,
+    // New transitions get the largest priority so far, so each is sampled at least once early on.
+    max_priority: Value,
+}
+
+impl ReplayBuffer {
+    /// Keeps the latest `capacity` transitions and samples `batch` of them at a time.
+    pub fn new(capacity: usize, batch: usize) -> ReplayBuffer {
+        let capacity = capacity.max(1);
+        ReplayBuffer { capacity, batch, sampling: Sampling::Uniform, transitions: Vec::with_capacity(capacity),
+            next: 0, tree: vec![0.; 2 * capacity], max_priority: 1. }
+    }
+
+    pub fn with_sampling(mut self, sampling: Sampling) -> ReplayBuffer {
+        self.sampling = sampling;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.transitions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transitions.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> &Transition {
+        &self.transitions[index]
+    }
+
+    pub fn push(&mut self, transition: Transition) {
+        let index = if self.transitions.len() < self.capacity {
+            self.transitions.push(transition);
+            self.transitions.len() - 1
+        }
+        else {
+            let index = self.next;
+            self.transitions[index] = transition;
+            self.next = (self.next + 1) % self.capacity;
+            index
+        };
+        self.set_priority(index, self.max_priority);
+    }
+
+    /// A mini-batch of transition indices, each with its importance-sampling weight.
+    pub fn sample(&self, rng: &mut RandomNumberGenerator) -> Vec<(usize, Value)> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+        let n = self.len();
+        match self.sampling {
+            Sampling::Uniform => (0..self.batch).map(|_| (rng.range(0, n), 1.)).collect(),
+            Sampling::Prioritized { beta, .. } => {
+                let total = self.tree[1];
+                let batch: Vec<(usize, Value)> = (0..self.batch)
+                    .map(|_| {
+                        let index = self.find(rng.range(0., total));
+                        let probability = self.tree[self.capacity + index] / total;
+                        (index, (n as Value * probability).powf(-beta))
+                    })
+                    .collect();
+                let max_weight = batch.iter().map(|(_, w)| *w).fold(0., Value::max);
+                batch.into_iter().map(|(index, w)| (index, w / max_weight)).collect()
+            },
+        }
+    }
+
+    /// Sets how likely a transition is to be sampled again from the TD error it just had.
+    pub fn update_priority(&mut self, index: usize, td_error: Value) {
+        // A little is added so no transition stops being sampled altogether.
+        let priority = td_error.abs() + 1e-3;
+        self.max_priority = self.max_priority.max(priority);
+        self.set_priority(index, priority);
+    }
+
+    fn set_priority(&mut self, index: usize, priority: Value) {
+        let alpha = match self.sampling {
+            Sampling::Uniform => 0.,
+            Sampling::Prioritized { alpha, .. } => alpha,
+        };
+        let mut node = self.capacity + index;
+        self.tree[node] = priority.powf(alpha);
+        while node > 1 {
+            node /= 2;
+            self.tree[node] = self.tree[2 * node] + self.tree[2 * node + 1];
+        }
+    }
+
+    /// The transition whose share of the total priority covers the given mass.
+    fn find(&self, mut mass: Value) -> usize {
+        let mut node = 1;
+        while node < self.capacity {
+            let left = 2 * node;
+            if mass < self.tree[left] || self.tree[left + 1] == 0. {
+                node = left;
+            }
+            else {
+                mass -= self.tree[left];
+                node = left + 1;
+            }
+        }
+        // Rounding must not land past the last filled slot.
+        (node - self.capacity).min(self.len() - 1)
+    }
+}
@@ -0,0 +1,117 @@
+/*
+Rewards handed out by the game.
+The end of a game pays the winner, the loser, or both players on a draw,
+every move can cost or pay a fixed amount, and an optional potential shapes each transition.
+Potential-based shaping adds gamma * potential(next) - potential(previous) to every move,
+from the board the player moved on to the board at its next turn, or the end of the game.
+The terms cancel out over each player's game, so shaping speeds up learning
+without changing which policy is optimal.
+*/
+
+use crate::piece::Piece;
+use crate::q_agent::Value;
+use crate::state::State;
+use crate::vector::Vector2;
+
+/// A heuristic value of a board for a player, used for shaping.
+pub type Potential = fn(&State, Piece) -> Value;
+
+#[derive(Clone, Copy)]
+pub struct RewardScheme {
+    pub win:    Value,
+    pub loss:   Value,
+    pub draw:   Value,
+    /// Paid for every move, e.g. a small negative value to prefer quick wins.
+    pub per_move: Value,
+    pub shaping: Option<Potential>,
+}
+
+impl RewardScheme {
+    pub fn new(win: Value, loss: Value, draw: Value) -> RewardScheme {
+        RewardScheme { win, loss, draw, per_move: 0., shaping: None }
+    }
+
+    pub fn with_per_move(mut self, per_move: Value) -> RewardScheme {
+        self.per_move = per_move;
+        self
+    }
+
+    /// Shapes every transition with the given potential.
+    pub fn with_shaping(mut self, potential: Potential) -> RewardScheme {
+        self.shaping = Some(potential);
+        self
+    }
+
+    /// The reward for a player's transition from one of its turns to the next, or to the end of the game:
+    /// the move's value, the result if the game is over, and the shaping term.
+    /// Finished boards have a potential of 0, so shaping cannot change the final result.
+    pub fn transition(&self, prev_state: &State, state: &State, player: Piece, gamma: Value) -> Value {
+        let winner = state.check_winner();
+        let next = if winner == Piece::Empty { self.potential(state, player) } else { 0. };
+        self.per_move + self.outcome(player, winner) + gamma * next - self.potential(prev_state, player)
+    }
+
+    /// The shaping potential of a board for a player, 0 without shaping.
+    pub fn potential(&self, state: &State, player: Piece) -> Value {
+        self.shaping.map_or(0., |potential| potential(state, player))
+    }
+
+    /// Does every move pay one player exactly what it costs the other?
+    /// Shaping and per-move rewards are paid to both players alike, so they never are.
+    pub fn is_zero_sum(&self) -> bool {
+        self.win == -self.loss && self.draw == 0. && self.per_move == 0. && self.shaping.is_none()
+    }
+
+    /// The reward for a player once the game has a result, 0 while it is still going.
+    pub fn outcome(&self, player: Piece, winner: Piece) -> Value {
+        match winner {
+            Piece::Empty => 0.,
+            Piece::Draw => self.draw,
+            _ if winner == player => self.win,
+            _ => self.loss,
+        }
+    }
+}
+
+impl Default for RewardScheme {
+    /// The rewards the game has always used.
+    fn default() -> RewardScheme {
+        RewardScheme::new(1.0, -0.5, 0.5)
+    }
+}
+
+/// The share of lines the player could still complete, minus the share the opponent could.
+/// A line is any `win_length` run of cells holding pieces of one player only.
+pub fn open_lines(state: &State, player: Piece) -> Value {
+    let rules = state.rules();
+    let size = rules.board_size;
+    let length = rules.win_length as i32;
+    let directions = [Vector2::new(0, 1), Vector2::new(1, 0), Vector2::new(1, 1), Vector2::new(1, -1)];
+    let mut lines = 0;
+    let mut balance = 0;
+    for i in 0..size.x {
+        for j in 0..size.y {
+            for step in directions.iter() {
+                let end = Vector2::new(i + step.x * (length - 1), j + step.y * (length - 1));
+                if end.x < 0 || end.x >= size.x || end.y < 0 || end.y >= size.y {
+                    continue;
+                }
+                lines += 1;
+                let pieces: Vec<Piece> = (0..length)
+                    .map(|k| state.get_piece(&Vector2::new(i + step.x * k, j + step.y * k)))
+                    .filter(|piece| *piece != Piece::Empty)
+                    .collect();
+                match pieces.first() {
+                    Some(first) if pieces.iter().all(|piece| piece == first) => {
+                        balance += if *first == player { 1 } else { -1 };
+                    },
+                    _ => (),
+                }
+            }
+        }
+    }
+    if lines == 0 {
+        return 0.;
+    }
+    balance as Value / lines as Value
+}
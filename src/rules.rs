@@ -0,0 +1,33 @@
+/*
+Rules describes the kind of game being played on a State:
+the size of the board, how many pieces in a row are needed to win,
+and whether pieces fall to the bottom of the column they are played in.
+*/
+
+use serde::{Serialize, Deserialize};
+use crate::vector::Vector2;
+
+#[derive(PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize)]
+pub struct Rules {
+    pub board_size: Vector2,
+    pub win_length: usize,
+    pub gravity: bool,
+}
+
+impl Rules {
+    pub fn new(board_size: Vector2, win_length: usize, gravity: bool) -> Rules {
+        assert!(win_length > 0 && win_length <= board_size.x.max(board_size.y) as usize,
+            "win length {} does not fit on the board", win_length);
+        Rules { board_size, win_length, gravity }
+    }
+
+    /// 3x3 board, three in a row.
+    pub fn tic_tac_toe() -> Rules {
+        Rules::new(Vector2::new(3, 3), 3, false)
+    }
+
+    /// 6 rows by 7 columns, four in a row, pieces drop down each column.
+    pub fn connect_four() -> Rules {
+        Rules::new(Vector2::new(6, 7), 4, true)
+    }
+}
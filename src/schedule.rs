@@ -0,0 +1,136 @@
+/*
+Schedules for parameters that change during training, such as epsilon, eta and the softmax temperature.
+Each schedule counts in steps (single moves), games or epochs, and the game advances them as it plays.
+An inverse-visit-count schedule instead falls off with the agent's visits of the state it is playing on.
+*/
+
+use std::f64::consts::PI;
+
+use crate::agent::Params;
+use crate::q_agent::Value;
+
+/// The value of a parameter after t ticks.
+#[derive(Clone, Copy)]
+pub enum Schedule {
+    Constant(Value),
+    /// Straight from start to end over the given ticks, then stays at end.
+    Linear { start: Value, end: Value, ticks: usize },
+    /// Multiplied by rate every tick, never going below min.
+    Exponential { start: Value, rate: Value, min: Value },
+    /// Multiplied by factor once every `every` ticks.
+    Step { start: Value, factor: Value, every: usize },
+    /// Lowered by delta on the first tick and every `every` ticks after, never going below min.
+    /// The decay `experiment` has always used for epsilon.
+    Decrement { start: Value, delta: Value, every: usize, min: Value },
+    /// Half a cosine from start to end over the given ticks, then stays at end.
+    Cosine { start: Value, end: Value, ticks: usize },
+    /// start / (1 + t / scale), falling off with time, the ticks seen so far.
+    /// Unlike `InverseVisits` it does not count the visits of each state.
+    InverseTime { start: Value, scale: Value },
+    /// start / (1 + N(s) / scale), falling off with N(s), how often the agent has played on the state,
+    /// from its own visit counts. Only epsilon and the temperature, which the agent applies per state,
+    /// can follow it; elsewhere it reads as start.
+    InverseVisits { start: Value, scale: Value },
+}
+
+impl Schedule {
+    pub fn value(&self, t: usize) -> Value {
+        match *self {
+            Schedule::Constant(value) => value,
+            Schedule::Linear { start, end, ticks } => {
+                let progress = (t as Value / ticks.max(1) as Value).min(1.);
+                start + (end - start) * progress
+            },
+            Schedule::Exponential { start, rate, min } => (start * rate.powi(t as i32)).max(min),
+            Schedule::Step { start, factor, every } => start * factor.powi((t / every.max(1)) as i32),
+            Schedule::Decrement { start, delta, every, min } => {
+                let every = every.max(1);
+                (start - delta * t.div_ceil(every) as Value).max(min)
+            },
+            Schedule::Cosine { start, end, ticks } => {
+                let progress = (t as Value / ticks.max(1) as Value).min(1.);
+                end + (start - end) * (1. + (PI * progress).cos()) / 2.
+            },
+            Schedule::InverseTime { start, scale } => start / (1. + t as Value / scale),
+            Schedule::InverseVisits { start, .. } => start,
+        }
+    }
+
+    /// The scale of an inverse-visit-count schedule, None for schedules that only count ticks.
+    pub fn visit_scale(&self) -> Option<Value> {
+        match *self {
+            Schedule::InverseVisits { scale, .. } => Some(scale),
+            _ => None,
+        }
+    }
+}
+
+/// The unit a schedule counts in.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Tick {
+    Step,
+    Game,
+    Epoch,
+}
+
+/// A schedule and how far along it is.
+#[derive(Clone, Copy)]
+pub struct Scheduled {
+    schedule: Schedule,
+    tick: Tick,
+    t: usize,
+}
+
+impl Scheduled {
+    pub fn new(schedule: Schedule, tick: Tick) -> Scheduled {
+        Scheduled { schedule, tick, t: 0 }
+    }
+
+    pub fn value(&self) -> Value {
+        self.schedule.value(self.t)
+    }
+
+    fn visit_scale(&self) -> Option<Value> {
+        self.schedule.visit_scale()
+    }
+
+    /// Moves one tick along if the schedule counts in this unit.
+    fn advance(&mut self, tick: Tick) {
+        if tick == self.tick {
+            self.t += 1;
+        }
+    }
+}
+
+/// The schedules a game follows, parameters without one keep their value.
+#[derive(Clone, Copy, Default)]
+pub struct Schedules {
+    pub epsilon:     Option<Scheduled>,
+    pub eta:         Option<Scheduled>,
+    pub temperature: Option<Scheduled>,
+}
+
+impl Schedules {
+    /// Writes the current value of every schedule into the parameters.
+    pub fn apply(&self, params: &mut Params) {
+        if let Some(epsilon) = &self.epsilon {
+            params.epsilon = epsilon.value();
+            params.epsilon_visits = epsilon.visit_scale();
+        }
+        if let Some(eta) = &self.eta {
+            params.eta = eta.value();
+        }
+        if let Some(temperature) = &self.temperature {
+            params.temperature = temperature.value();
+            params.temperature_visits = temperature.visit_scale();
+        }
+    }
+
+    /// Advances the schedules counting in this unit and updates the parameters.
+    pub fn advance(&mut self, tick: Tick, params: &mut Params) {
+        for scheduled in [&mut self.epsilon, &mut self.eta, &mut self.temperature].into_iter().flatten() {
+            scheduled.advance(tick);
+        }
+        self.apply(params);
+    }
+}
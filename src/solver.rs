@@ -0,0 +1,140 @@
+/*
+Perfect-play solver for small boards.
+Negamax with alpha-beta pruning and a transposition table keyed on State.
+Values are from the point of view of the player on the play: 1 win, 0 draw, -1 loss.
+Full solves are only practical for small boards such as 3x3 or 4x4;
+the table grows with every position searched.
+*/
+
+use std::any::Any;
+use std::collections::HashMap;
+use bracket_lib::random::RandomNumberGenerator;
+
+use crate::agent::{Agent, Params};
+use crate::piece::Piece;
+use crate::q_agent::Value;
+use crate::state::*;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Bound {
+    Exact,
+    Lower,
+    Upper,
+}
+
+/// The value of a position and every move that keeps it.
+pub struct Solution {
+    pub value: i32,
+    pub moves: Vec<Action>,
+}
+
+pub struct Solver {
+    table: HashMap<State, (i32, Bound), KeyHash>,
+}
+
+impl Solver {
+    pub fn new() -> Solver {
+        Solver { table: HashMap::default() }
+    }
+
+    /// The game-theoretic value of a position for the player on the play.
+    pub fn value(&mut self, state: &State) -> i32 {
+        self.negamax(state, -1, 1)
+    }
+
+    /// Finds the value of a position and the set of optimal moves.
+    pub fn solve(&mut self, state: &State) -> Solution {
+        let mut value = i32::MIN;
+        let mut moves = Vec::new();
+        for action in state.legal_actions(state.on_play()) {
+            let mut child = state.clone();
+            child.play_mut(&action);
+            let v = -self.value(&child);
+            if v > value {
+                value = v;
+                moves.clear();
+            }
+            if v == value {
+                moves.push(action);
+            }
+        }
+        Solution { value, moves }
+    }
+
+    fn negamax(&mut self, state: &State, mut alpha: i32, mut beta: i32) -> i32 {
+        // The last move ended the game, so a winner is always the player who just moved.
+        match state.check_winner() {
+            Piece::Empty => (),
+            Piece::Draw => return 0,
+            _ => return -1,
+        }
+
+        let alpha_orig = alpha;
+        if let Some((value, bound)) = self.table.get(state) {
+            match bound {
+                Bound::Exact => return *value,
+                Bound::Lower => alpha = alpha.max(*value),
+                Bound::Upper => beta = beta.min(*value),
+            }
+            if alpha >= beta {
+                return *value;
+            }
+        }
+
+        let mut best = -1;
+        for action in state.legal_actions(state.on_play()) {
+            let mut child = state.clone();
+            child.play_mut(&action);
+            best = best.max(-self.negamax(&child, -beta, -alpha));
+            alpha = alpha.max(best);
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        let bound = if best <= alpha_orig {
+            Bound::Upper
+        }
+        else if best >= beta {
+            Bound::Lower
+        }
+        else {
+            Bound::Exact
+        };
+        self.table.insert(state.clone(), (best, bound));
+        best
+    }
+}
+
+/// Plays a random optimal move, or with probability `blunder` a random legal move.
+pub struct MinimaxAgent {
+    player: Piece,
+    solver: Solver,
+    blunder: Value,
+}
+
+impl MinimaxAgent {
+    pub fn new(player: Piece, blunder: Value) -> MinimaxAgent {
+        MinimaxAgent { player, solver: Solver::new(), blunder }
+    }
+}
+
+impl Agent for MinimaxAgent {
+    fn player(&self) -> Piece {
+        self.player
+    }
+
+    fn choose_action(&mut self, state: &State, rng: &mut RandomNumberGenerator, _params: &Params) -> Option<Action> {
+        let moves = if rng.range(0., 1.) < self.blunder {
+            state.legal_actions(self.player)
+        }
+        else {
+            self.solver.solve(state).moves
+        };
+        Some(moves[rng.range(0, moves.len())])
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
@@ -1,66 +1,220 @@
-use ndarray::prelude::*;
+use std::fmt;
+use std::hash::{BuildHasherDefault, Hash, Hasher};
+use serde::{Serialize, Deserialize};
 use crate::vector::Vector2;
 use crate::piece::Piece;
+use crate::rules::Rules;
+use crate::symmetry::Symmetry;
 
-#[derive(PartialEq, Eq, Hash, Clone, Copy)]
+/// A move on the board.
+/// Under gravity rules only the column (`position.y`) is used,
+/// the piece lands on the lowest empty cell of that column.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize)]
 pub struct Action {
     pub player: Piece,
     pub position: Vector2,
 }
 
-#[derive(PartialEq, Eq, Clone, Hash)]
+/// The board as a pair of bitboards, one per player, indexed row by row.
+/// Boards can hold up to 64 cells (8x8).
+/// `hash` is a Zobrist hash of the pieces and the side to move, kept up to date by `play_mut`.
+#[derive(PartialEq, Eq, Clone)]
 pub struct State {
-    map: Array2<Piece>,
+    boards: [u64; 2],
     on_play: Piece,
+    winner: Piece,
+    rules: Rules,
+    hash: u64,
+}
+
+const MAX_CELLS: usize = 64;
+
+/// Random keys for every (player, cell) pair, plus one for player 2 being on the play.
+const ZOBRIST: [[u64; MAX_CELLS]; 2] = zobrist_keys();
+const ZOBRIST_P2: u64 = splitmix64(2 * MAX_CELLS as u64);
+
+const fn zobrist_keys() -> [[u64; MAX_CELLS]; 2] {
+    let mut keys = [[0; MAX_CELLS]; 2];
+    let mut i = 0;
+    while i < MAX_CELLS {
+        keys[0][i] = splitmix64(i as u64);
+        keys[1][i] = splitmix64((MAX_CELLS + i) as u64);
+        i += 1;
+    }
+    keys
+}
+
+/// Fixed pseudo-random mixing so the keys are the same on every run.
+const fn splitmix64(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(1).wrapping_mul(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+/// Hashes table keys made of states, whose Zobrist hash is already well mixed,
+/// with one rotate, xor and multiply per word instead of SipHash.
+#[derive(Default)]
+pub struct KeyHasher(u64);
+
+/// Builds a `KeyHasher` for the tables keyed on states.
+pub type KeyHash = BuildHasherDefault<KeyHasher>;
+
+impl KeyHasher {
+    fn add(&mut self, word: u64) {
+        self.0 = (self.0.rotate_left(5) ^ word).wrapping_mul(0x51_7C_C1_B7_27_22_0A_95);
+    }
+}
+
+impl Hasher for KeyHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.add(*byte as u64);
+        }
+    }
+
+    fn write_u8(&mut self, n: u8) {
+        self.add(n as u64);
+    }
+
+    fn write_u32(&mut self, n: u32) {
+        self.add(n as u64);
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.add(n);
+    }
+
+    fn write_usize(&mut self, n: usize) {
+        self.add(n as u64);
+    }
+}
+
+impl Hash for State {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.hash);
+    }
 }
 
 impl State {
 
     // PUBLIC
-    pub fn new(board_size: Vector2) -> State {
-        let map = Array2::from_elem(board_size.dim(), Piece::Empty);
-        let on_play = Piece::P1;
-        State { map, on_play }
+    /// Creates an empty board for the given rules.
+    pub fn new(rules: Rules) -> State {
+        let cells = (rules.board_size.x * rules.board_size.y) as usize;
+        assert!(cells <= MAX_CELLS, "boards are limited to {} cells", MAX_CELLS);
+        State { boards: [0; 2], on_play: Piece::P1, winner: Piece::Empty, rules, hash: 0 }
     }
 
     pub fn reset(&mut self) {
+        self.boards = [0; 2];
         self.on_play = Piece::P1;
-        for piece in self.map.iter_mut() {
-            *piece = Piece::Empty;
-        }
+        self.winner = Piece::Empty;
+        self.hash = 0;
     }
 
     /// Checks the action and performs it on the state, changing state in-place.
     pub fn play_mut(&mut self, action: &Action) {
-        if self.is_in_bounds(&action.position) && self.is_legal_move(action) {
-            self.modify_state(action);
+        if let Some(position) = self.landing(action) {
+            self.modify_state(action.player, &position);
         }
     }
 
+    /// Can this action be played on the current board?
+    pub fn is_legal(&self, action: &Action) -> bool {
+        self.landing(action).is_some()
+    }
+
+    /// Finds the cell an action would fill, if the action is legal.
+    pub fn landing(&self, action: &Action) -> Option<Vector2> {
+        if self.rules.gravity {
+            self.drop_position(action.position.y)
+        }
+        else if self.is_in_bounds(&action.position) && self.is_legal_move(action) {
+            Some(action.position)
+        }
+        else {
+            None
+        }
+    }
+
+    /// Lists every action the player could legally play here.
+    /// Under gravity rules there is one action per open column.
+    pub fn legal_actions(&self, player: Piece) -> Vec<Action> {
+        let size = self.rules.board_size;
+        // Under gravity only the top row's columns are listed.
+        let rows = if self.rules.gravity { 1 } else { size.x };
+        (0..rows)
+            .flat_map(|i| (0..size.y).map(move |j| Vector2::new(i, j)))
+            .map(|position| Action { player, position })
+            .filter(|action| self.is_legal(action))
+            .collect()
+    }
+
     /// Checks if this state has any empty spaces left.
     pub fn terminal(&self) -> bool {
-        for spot in self.map.iter() {
-            if *spot == Piece::Empty {
-                return false;
-            }
-        }
-        true
+        (self.boards[0] | self.boards[1]) == self.full_mask()
     }
 
     /// Returns a piece given a position vector.
     pub fn get_piece(&self, position: &Vector2) -> Piece {
-        let index = position.index();
-        self.map[[index.0, index.1]]
+        let bit = self.bit(position);
+        if self.boards[0] & bit != 0 {
+            Piece::P1
+        }
+        else if self.boards[1] & bit != 0 {
+            Piece::P2
+        }
+        else {
+            Piece::Empty
+        }
     }
 
     /// Reports the current state of the game.
     /// Returns the winner.
     pub fn check_winner(&self) -> Piece {
-        let mut winner = self.check_lines();
-        if self.terminal() && winner == Piece::Empty {
-            winner = Piece::Draw;
+        if self.winner == Piece::Empty && self.terminal() {
+            return Piece::Draw;
         }
-        winner
+        self.winner
+    }
+
+    /// Returns a copy of this state with every piece moved by a symmetry.
+    pub fn transformed(&self, symmetry: Symmetry) -> State {
+        let size = self.rules.board_size;
+        let mut state = State::new(self.rules);
+        for i in 0..size.x {
+            for j in 0..size.y {
+                let position = Vector2::new(i, j);
+                let piece = self.get_piece(&position);
+                if piece != Piece::Empty {
+                    state.set_piece(piece, &symmetry.apply(position, size));
+                }
+            }
+        }
+        state.set_on_play(self.on_play);
+        state.winner = self.winner;
+        state
+    }
+
+    /// Finds the smallest board among the given symmetries of this state.
+    /// Returns that canonical state and the symmetry that produced it.
+    pub fn canonical(&self, symmetries: &[Symmetry]) -> (State, Symmetry) {
+        let mut best = (self.clone(), Symmetry::Identity);
+        for symmetry in symmetries.iter() {
+            if *symmetry == Symmetry::Identity {
+                continue;
+            }
+            let candidate = self.transformed(*symmetry);
+            if candidate.boards < best.0.boards {
+                best = (candidate, *symmetry);
+            }
+        }
+        best
     }
 
     /// Which player is on the play?
@@ -68,102 +222,162 @@ impl State {
         self.on_play
     }
 
+    /// The rules this board is played under.
+    pub fn rules(&self) -> Rules {
+        self.rules
+    }
+
+    /// Writes the board one string per row, `X` and `O` for pieces and `.` for empty cells.
+    pub fn rows(&self) -> Vec<String> {
+        let size = self.rules.board_size;
+        (0..size.x).map(|i| {
+            (0..size.y).map(|j| match self.get_piece(&Vector2::new(i, j)) {
+                Piece::P1 => 'X',
+                Piece::P2 => 'O',
+                _ => '.',
+            }).collect()
+        }).collect()
+    }
+
+    /// Rebuilds a state from the strings written by `rows`.
+    /// Returns None if the rows do not fit the board.
+    pub fn from_rows(rules: Rules, rows: &[String], on_play: Piece) -> Option<State> {
+        let size = rules.board_size;
+        if rows.len() != size.x as usize {
+            return None;
+        }
+        let mut state = State::new(rules);
+        let mut placed = Vec::new();
+        for (i, row) in rows.iter().enumerate() {
+            if row.chars().count() != size.y as usize {
+                return None;
+            }
+            for (j, c) in row.chars().enumerate() {
+                let piece = match c {
+                    'X' => Piece::P1,
+                    'O' => Piece::P2,
+                    '.' => continue,
+                    _ => return None,
+                };
+                let position = Vector2::new(i as i32, j as i32);
+                state.set_piece(piece, &position);
+                placed.push((piece, position));
+            }
+        }
+        for (piece, position) in placed.iter() {
+            if state.winner == Piece::Empty && state.check_lines(*piece, position) {
+                state.winner = *piece;
+            }
+        }
+        state.set_on_play(on_play);
+        Some(state)
+    }
+
 }
 
 impl State {
     // PRIVATE
 
     fn is_in_bounds(&self, vector: &Vector2) -> bool {
-        let dim = self.map.dim();
-        vector.x >= 0 && vector.x < dim.0 as i32 &&
-        vector.y >= 0 && vector.y < dim.1 as i32
+        let size = self.rules.board_size;
+        vector.x >= 0 && vector.x < size.x &&
+        vector.y >= 0 && vector.y < size.y
     }
     
     fn is_legal_move(&self, action: &Action) -> bool {
-        let index = action.position.index();
-        self.map[[index.0, index.1]] == Piece::Empty
+        self.is_empty(&action.position)
     }
 
-    /// In-place morphing of the state.
-    fn modify_state(&mut self, action: &Action) {
-        let index = action.position.index();
-        self.map[[index.0, index.1]] = action.player;
-        self.on_play = match action.player {
-            Piece::P1 => Piece::P2,
-            Piece::P2 => Piece::P1,
-            _ => Piece::Empty,
-        };
-    }
-
-    /// Checks the state to see if any player has won yet.
-    fn check_lines(&self) -> Piece {
-        // check rows
-        for line in self.map.rows() {
-            let result = Self::check_line(line);
-            if result != Piece::Empty {
-                return result;
-            }
-        }
-        // check columns
-        for line in self.map.columns() {
-            let result = Self::check_line(line);
-            if result != Piece::Empty {
-                return result;
-            }
-        }
-        // normal diagonal
-        let result = Self::check_line(self.map.diag());
-        if result != Piece::Empty {
-            return result;
-        }
+    fn is_empty(&self, position: &Vector2) -> bool {
+        (self.boards[0] | self.boards[1]) & self.bit(position) == 0
+    }
 
-        // weird diagonal
-        let result = Self::check_diag(&self.map);
-        if result != Piece::Empty {
-            return result;
-        }
+    /// The bit for a cell, counting along each row.
+    fn bit(&self, position: &Vector2) -> u64 {
+        1 << self.cell(position)
+    }
 
-        Piece::Empty
+    fn cell(&self, position: &Vector2) -> usize {
+        (position.x * self.rules.board_size.y + position.y) as usize
     }
 
-    fn check_line(line: ArrayView1<Piece>) -> Piece {
-        let first = line[0];
-        if first == Piece::Empty {
-            return Piece::Empty;
-        }
-        for piece in line {
-            if *piece != first {
-                return Piece::Empty;
-            }
+    /// Every cell on the board set.
+    fn full_mask(&self) -> u64 {
+        let cells = self.rules.board_size.x * self.rules.board_size.y;
+        u64::MAX >> (MAX_CELLS as i32 - cells)
+    }
+
+    /// Finds the lowest empty cell in a column.
+    fn drop_position(&self, column: i32) -> Option<Vector2> {
+        let rows = self.rules.board_size.x;
+        (0..rows).rev()
+            .map(|row| Vector2::new(row, column))
+            .find(|position| self.is_in_bounds(position) && self.is_empty(position))
+    }
+
+    /// In-place morphing of the state.
+    fn modify_state(&mut self, player: Piece, position: &Vector2) {
+        self.set_piece(player, position);
+        if self.winner == Piece::Empty && self.check_lines(player, position) {
+            self.winner = player;
         }
-        first
+        self.set_on_play(player.opponent());
     }
 
-    fn check_diag(map: &Array2<Piece>) -> Piece {
-        let dim = map.dim();
-        let first = map[[0, dim.1 - 1]];
-        if first == Piece::Empty {
-            return Piece::Empty;
+    /// Places a piece, updating the bitboards and the hash.
+    fn set_piece(&mut self, player: Piece, position: &Vector2) {
+        let index = player.to_index();
+        let cell = self.cell(position);
+        self.boards[index] |= 1 << cell;
+        self.hash ^= ZOBRIST[index][cell];
+    }
+
+    /// Changes the side to move, updating the hash.
+    fn set_on_play(&mut self, on_play: Piece) {
+        if (self.on_play == Piece::P2) != (on_play == Piece::P2) {
+            self.hash ^= ZOBRIST_P2;
         }
-        let mut j = dim.1;
-        for i in 0..dim.0 {
-            j -= 1;
-            if map[[i, j]] != first {
-                return Piece::Empty;
-            }
+        self.on_play = on_play;
+    }
+
+    /// Checks if the piece just placed at `position` completes a run of `win_length`.
+    /// Only lines through the new piece can have changed, so this is all we need to look at.
+    fn check_lines(&self, piece: Piece, position: &Vector2) -> bool {
+        // horizontal, vertical and both diagonals
+        let directions = [
+            Vector2::new(0, 1),
+            Vector2::new(1, 0),
+            Vector2::new(1, 1),
+            Vector2::new(1, -1),
+        ];
+        directions.iter().any(|step| {
+            let back = Vector2::new(0, 0) - *step;
+            let run = 1 + self.count_run(*position, *step, piece) + self.count_run(*position, back, piece);
+            run >= self.rules.win_length
+        })
+    }
+
+    /// Counts matching pieces walking away from `start`, not including it.
+    fn count_run(&self, start: Vector2, step: Vector2, piece: Piece) -> usize {
+        let board = self.boards[piece.to_index()];
+        let mut position = start + step;
+        let mut count = 0;
+        while self.is_in_bounds(&position) && board & self.bit(&position) != 0 {
+            count += 1;
+            position = position + step;
         }
-        first
+        count
     }
 
 }
 
-impl ToString for State {
-    fn to_string(&self) -> String {
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut string = String::new();
-        for row in self.map.rows() {
-            for col in row {
+        for i in 0..self.rules.board_size.x {
+            for j in 0..self.rules.board_size.y {
                 let str = 
-                match *col {
+                match self.get_piece(&Vector2::new(i, j)) {
                     Piece::P1 => "-X-",
                     Piece::P2 => "-O-",
                     Piece::Empty => "|_|",
@@ -173,6 +387,119 @@ impl ToString for State {
             }
             string.push('\n');    
         }
-        string
+        write!(f, "{}", string)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Plays the moves in turn from an empty board, P1 first.
+    fn played(rules: Rules, moves: &[(i32, i32)]) -> State {
+        let mut state = State::new(rules);
+        for &(x, y) in moves {
+            let action = Action { player: state.on_play(), position: Vector2::new(x, y) };
+            assert!(state.is_legal(&action));
+            state.play_mut(&action);
+        }
+        state
+    }
+
+    /// The hash of a state as if it were built from scratch.
+    fn rebuilt_hash(state: &State) -> u64 {
+        State::from_rows(state.rules(), &state.rows(), state.on_play()).unwrap().hash
+    }
+
+    #[test]
+    fn incremental_hash_matches_rebuilt_states() {
+        let rules = Rules::tic_tac_toe();
+        let state = played(rules, &[(1, 1), (0, 0), (2, 0), (0, 2)]);
+        assert_eq!(state.hash, rebuilt_hash(&state));
+
+        for symmetry in Symmetry::ALL {
+            let transformed = state.transformed(symmetry);
+            assert_eq!(transformed.hash, rebuilt_hash(&transformed));
+        }
+        assert_eq!(state.transformed(Symmetry::Identity).hash, state.hash);
+
+        // The same board with the other side to move hashes differently.
+        let other = State::from_rows(rules, &state.rows(), Piece::P2).unwrap();
+        assert_ne!(other.hash, state.hash);
+
+        let mut reset = state.clone();
+        reset.reset();
+        assert_eq!(reset.hash, State::new(rules).hash);
+    }
+
+    #[test]
+    fn incremental_hash_matches_rebuilt_states_under_gravity() {
+        let state = played(Rules::connect_four(), &[(0, 3), (0, 3), (0, 4), (0, 2), (0, 3)]);
+        assert_eq!(state.hash, rebuilt_hash(&state));
+        let mirrored = state.transformed(Symmetry::FlipColumns);
+        assert_eq!(mirrored.hash, rebuilt_hash(&mirrored));
+    }
+
+    #[test]
+    fn finds_every_direction_of_three_in_a_row() {
+        let rules = Rules::tic_tac_toe();
+        // Row, column, diagonal and anti-diagonal, P1 on the even moves.
+        let wins: [&[(i32, i32)]; 4] = [
+            &[(0, 0), (1, 0), (0, 1), (1, 1), (0, 2)],
+            &[(0, 1), (0, 0), (1, 1), (0, 2), (2, 1)],
+            &[(0, 0), (0, 1), (1, 1), (0, 2), (2, 2)],
+            &[(0, 2), (0, 0), (1, 1), (0, 1), (2, 0)],
+        ];
+        for moves in wins {
+            let state = played(rules, moves);
+            assert!(state.check_winner() == Piece::P1);
+            assert!(State::from_rows(rules, &state.rows(), state.on_play()).unwrap().check_winner() == Piece::P1);
+        }
+    }
+
+    #[test]
+    fn lines_do_not_wrap_between_rows() {
+        // The last cell of the top row sits next to the first of the middle row in the bitboard.
+        let state = played(Rules::tic_tac_toe(), &[(0, 2), (2, 2), (1, 0), (2, 1), (1, 1)]);
+        assert!(state.check_winner() == Piece::Empty);
+    }
+
+    #[test]
+    fn draws_on_a_full_board_without_a_line() {
+        let state = played(Rules::tic_tac_toe(),
+            &[(0, 0), (1, 1), (2, 2), (0, 1), (2, 1), (2, 0), (0, 2), (1, 2), (1, 0)]);
+        assert!(state.terminal());
+        assert!(state.check_winner() == Piece::Draw);
+    }
+
+    #[test]
+    fn pieces_fall_and_connect_four() {
+        let rules = Rules::connect_four();
+        let state = played(rules, &[(0, 3), (0, 3), (0, 3)]);
+        assert!(state.get_piece(&Vector2::new(5, 3)) == Piece::P1);
+        assert!(state.get_piece(&Vector2::new(4, 3)) == Piece::P2);
+        assert!(state.get_piece(&Vector2::new(3, 3)) == Piece::P1);
+
+        // Four down a column.
+        let vertical = played(rules, &[(0, 0), (0, 1), (0, 0), (0, 1), (0, 0), (0, 1), (0, 0)]);
+        assert!(vertical.check_winner() == Piece::P1);
+
+        // Three in a row is not enough.
+        let three = played(rules, &[(0, 0), (0, 0), (0, 1), (0, 1), (0, 2)]);
+        assert!(three.check_winner() == Piece::Empty);
+
+        // A rising diagonal, from the bottom left.
+        let diagonal = played(rules,
+            &[(0, 0), (0, 1), (0, 1), (0, 2), (0, 2), (0, 3), (0, 2), (0, 3), (0, 3), (0, 6), (0, 3)]);
+        assert!(diagonal.check_winner() == Piece::P1);
+    }
+
+    #[test]
+    fn full_columns_are_not_legal() {
+        let rules = Rules::connect_four();
+        let state = played(rules, &[(0, 0); 6]);
+        let full = Action { player: state.on_play(), position: Vector2::new(0, 0) };
+        assert!(!state.is_legal(&full));
+        assert_eq!(state.legal_actions(state.on_play()).len(), 6);
     }
 }
@@ -0,0 +1,102 @@
+/*
+Symmetry is one of the eight rotations and reflections of a board (the dihedral group).
+Positions that differ only by a symmetry play out the same way,
+so the Q-table can store them once under a canonical form.
+*/
+
+use crate::rules::Rules;
+use crate::state::{State, Action};
+use crate::vector::Vector2;
+
+#[derive(PartialEq, Eq, Clone, Copy, Hash, Debug)]
+pub enum Symmetry {
+    Identity,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    FlipRows,
+    FlipColumns,
+    Transpose,
+    AntiTranspose,
+}
+
+impl Symmetry {
+    pub const ALL: [Symmetry; 8] = [
+        Symmetry::Identity,
+        Symmetry::Rotate90,
+        Symmetry::Rotate180,
+        Symmetry::Rotate270,
+        Symmetry::FlipRows,
+        Symmetry::FlipColumns,
+        Symmetry::Transpose,
+        Symmetry::AntiTranspose,
+    ];
+
+    /// Lists the symmetries that map a board onto itself under the given rules.
+    /// Gravity only allows a left-right mirror, non-square boards lose the quarter turns.
+    pub fn valid(rules: Rules) -> Vec<Symmetry> {
+        let size = rules.board_size;
+        if rules.gravity {
+            vec![Symmetry::Identity, Symmetry::FlipColumns]
+        }
+        else if size.x == size.y {
+            Symmetry::ALL.to_vec()
+        }
+        else {
+            vec![Symmetry::Identity, Symmetry::Rotate180, Symmetry::FlipRows, Symmetry::FlipColumns]
+        }
+    }
+
+    /// Moves a position on a board of the given size.
+    pub fn apply(self, position: Vector2, size: Vector2) -> Vector2 {
+        let (x, y) = (position.x, position.y);
+        let (last_x, last_y) = (size.x - 1, size.y - 1);
+        match self {
+            Symmetry::Identity => Vector2::new(x, y),
+            Symmetry::Rotate90 => Vector2::new(y, last_x - x),
+            Symmetry::Rotate180 => Vector2::new(last_x - x, last_y - y),
+            Symmetry::Rotate270 => Vector2::new(last_y - y, x),
+            Symmetry::FlipRows => Vector2::new(last_x - x, y),
+            Symmetry::FlipColumns => Vector2::new(x, last_y - y),
+            Symmetry::Transpose => Vector2::new(y, x),
+            Symmetry::AntiTranspose => Vector2::new(last_y - y, last_x - x),
+        }
+    }
+
+}
+
+/// The symmetries an agent stores its boards under, and the table keys they give.
+#[derive(Clone)]
+pub struct Symmetries {
+    rules:      Rules,
+    symmetries: Vec<Symmetry>,
+}
+
+impl Symmetries {
+    /// Every symmetry valid under the rules when enabled, otherwise only the identity.
+    pub fn new(rules: Rules, enabled: bool) -> Symmetries {
+        let symmetries = if enabled { Symmetry::valid(rules) } else { vec![Symmetry::Identity] };
+        Symmetries { rules, symmetries }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.symmetries.len() > 1
+    }
+
+    /// The canonical board for a state and the symmetry that leads to it.
+    pub fn canonical(&self, state: &State) -> (State, Symmetry) {
+        state.canonical(&self.symmetries)
+    }
+
+    /// Moves an action onto the board the symmetry leads to.
+    pub fn action(&self, action: &Action, symmetry: Symmetry) -> Action {
+        Action { player: action.player, position: symmetry.apply(action.position, self.rules.board_size) }
+    }
+
+    /// The table key for a state and action, on the canonical board.
+    pub fn key(&self, state: &State, action: &Action) -> (State, Action) {
+        let (canonical, symmetry) = self.canonical(state);
+        let action = self.action(action, symmetry);
+        (canonical, action)
+    }
+}
@@ -1,7 +1,8 @@
 use std::ops;
+use serde::{Serialize, Deserialize};
 
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 pub struct Vector2 {
     pub x: i32, pub y: i32
 }